@@ -19,6 +19,7 @@ fn main() {
         println!("lsb_swc_sort:\t{:?}s", test_threads(threads, move || test_radix::<u64, LSBSWCRadixSorter<_>>(size, iters)));
         println!("msb_sort:\t{:?}s", test_threads(threads, move || test_radix::<u64, MSBRadixSorter<_>>(size, iters)));
         println!("msb_swc_sort:\t{:?}s", test_threads(threads, move || test_radix::<u64, MSBSWCRadixSorter<_>>(size, iters)));
+        println!("msb_par_sort:\t{:?}s", test_threads(threads, move || test_radix::<u64, ParallelMSBRadixSorter<_>>(size, iters)));
     }
     else {
         println!("usage: profile <size> <iterations> <threads>");
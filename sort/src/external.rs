@@ -0,0 +1,313 @@
+//! External radix sorting, spilling sorted runs to disk.
+//!
+//! The external sorter buffers pushed elements in memory until they reach a memory limit, at which
+//! point it radix sorts them with an MSB sorter and writes the sorted run to a temporary file. When
+//! finished, it sorts the elements still in memory and performs a k-way merge of all runs, reading
+//! each spilled run back a block at a time and handing sorted batches to the caller as they fill.
+//!
+//! Elements are written to and read from the spill files with `bincode`, which is why they must be
+//! serializable. The files are anonymous and only ever read back by the process that wrote them.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek, SeekFrom};
+use std::mem::{replace, size_of};
+use std::path::PathBuf;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::{Unsigned, RadixSorter, RadixSorterBase};
+use crate::msb::Sorter as MSBSorter;
+
+/// The number of bytes of elements buffered before a run is spilled, unless otherwise specified.
+const DEFAULT_MEMORY_LIMIT: usize = 1 << 28;
+
+/// The number of elements in each batch of merged output, and in each block read from a run.
+const BATCH_SIZE: usize = 1 << 13;
+
+/// A radix sorter that spills sorted runs to temporary files once its buffered elements reach a
+/// memory limit, and merges the runs when finished.
+///
+/// The sorter implements `RadixSorter`, so that it can stand in for the in-memory sorters, but the
+/// `finish_into` method must collect all sorted elements in memory. The `finish_with` method instead
+/// hands each sorted batch to a closure as the merge produces it.
+pub struct Sorter<T> {
+    sorter: MSBSorter<T>,       // sorts each run before it is spilled.
+    buffer: Vec<Vec<T>>,        // elements not yet spilled.
+    tail: Vec<T>,               // the batch currently receiving elements.
+    buffered: usize,            // the number of elements in `buffer` and `tail`.
+    limit: usize,               // the number of elements to buffer before spilling.
+    directory: Option<PathBuf>, // where to create spill files, if not the default temporary directory.
+    runs: Vec<(File, usize)>,   // spilled runs and their numbers of elements.
+}
+
+impl<T: Serialize+DeserializeOwned> Sorter<T> {
+
+    /// Allocates a sorter that spills once its buffered elements occupy `bytes` bytes in memory.
+    pub fn with_memory_limit(bytes: usize) -> Self {
+        let limit = ::std::cmp::max(bytes / ::std::cmp::max(size_of::<T>(), 1), 1);
+        Sorter {
+            sorter: MSBSorter::new(),
+            buffer: Vec::new(),
+            tail: Vec::with_capacity(BATCH_SIZE),
+            buffered: 0,
+            limit,
+            directory: None,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Creates spill files in `directory` rather than in the default temporary directory.
+    pub fn in_directory<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// The number of runs spilled to disk in the current sorting session.
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Completes the sorting session, handing sorted batches of elements to `consume` in order.
+    pub fn finish_with<U: Unsigned, F: Fn(&T)->U, C: FnMut(Vec<T>)>(&mut self, key: &F, mut consume: C) {
+
+        self.seal();
+        self.sorter.sort(&mut self.buffer, key);
+        self.buffered = 0;
+
+        if self.runs.is_empty() {
+            for batch in self.buffer.drain(..) {
+                consume(batch);
+            }
+            return;
+        }
+
+        let mut runs = Vec::with_capacity(self.runs.len() + 1);
+        for (file, length) in self.runs.drain(..) {
+            runs.push(Run::spilled(file, length));
+        }
+        runs.push(Run::in_memory(replace(&mut self.buffer, Vec::new())));
+
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (index, run) in runs.iter_mut().enumerate() {
+            if let Some(element) = run.peek() {
+                heap.push(Reverse((key(element), index)));
+            }
+        }
+
+        let mut output = Vec::with_capacity(BATCH_SIZE);
+        while let Some(Reverse((_, index))) = heap.pop() {
+            output.push(runs[index].pop());
+            if let Some(element) = runs[index].peek() {
+                heap.push(Reverse((key(element), index)));
+            }
+            if output.len() == BATCH_SIZE {
+                consume(replace(&mut output, Vec::with_capacity(BATCH_SIZE)));
+            }
+        }
+        if !output.is_empty() {
+            consume(output);
+        }
+    }
+
+    /// Moves a non-empty tail into the buffer.
+    fn seal(&mut self) {
+        if !self.tail.is_empty() {
+            let tail = replace(&mut self.tail, Vec::with_capacity(BATCH_SIZE));
+            self.buffer.push(tail);
+        }
+    }
+
+    /// Sorts the buffered elements and writes them to a new spill file.
+    fn spill<U: Unsigned, F: Fn(&T)->U>(&mut self, key: &F) {
+
+        self.seal();
+        self.sorter.sort(&mut self.buffer, key);
+
+        let file = match self.directory {
+            Some(ref directory) => ::tempfile::tempfile_in(directory),
+            None => ::tempfile::tempfile(),
+        };
+        let mut writer = BufWriter::new(file.expect("failed to create spill file"));
+        for element in self.buffer.iter().flat_map(|batch| batch.iter()) {
+            ::bincode::serialize_into(&mut writer, element).expect("failed to write spill file");
+        }
+        let mut file = writer.into_inner().expect("failed to write spill file");
+        file.seek(SeekFrom::Start(0)).expect("failed to rewind spill file");
+
+        self.runs.push((file, self.buffered));
+        self.buffered = 0;
+        self.sorter.rebalance(&mut self.buffer, 256);
+        self.buffer.clear();
+    }
+}
+
+impl<T: Serialize+DeserializeOwned, U: Unsigned> RadixSorter<T, U> for Sorter<T> {
+
+    #[inline]
+    fn push<F: Fn(&T)->U>(&mut self, element: T, key: &F) {
+        if self.tail.len() == self.tail.capacity() {
+            self.seal();
+        }
+        self.tail.push(element);
+        self.buffered += 1;
+        if self.buffered >= self.limit {
+            self.spill(key);
+        }
+    }
+
+    fn push_batch<F: Fn(&T)->U>(&mut self, batch: Vec<T>, key: &F) {
+        if !batch.is_empty() {
+            self.seal();
+            self.buffered += batch.len();
+            self.buffer.push(batch);
+            if self.buffered >= self.limit {
+                self.spill(key);
+            }
+        }
+    }
+
+    fn finish_into<F: Fn(&T)->U>(&mut self, target: &mut Vec<Vec<T>>, key: &F) {
+        self.finish_with(key, |batch| target.push(batch));
+    }
+}
+
+impl<T: Serialize+DeserializeOwned> RadixSorterBase<T> for Sorter<T> {
+    fn new() -> Self {
+        Sorter::with_memory_limit(DEFAULT_MEMORY_LIMIT)
+    }
+    fn rebalance(&mut self, buffers: &mut Vec<Vec<T>>, intended: usize) {
+        self.sorter.rebalance(buffers, intended);
+    }
+}
+
+/// A sorted run being merged, with its next elements in memory.
+struct Run<T> {
+    block: Vec<T>,              // elements read but not yet merged, in reverse order.
+    batches: Vec<Vec<T>>,       // in-memory batches not yet merged, in reverse order.
+    file: Option<BufReader<File>>,
+    remaining: usize,           // elements of the file not yet read.
+}
+
+impl<T: DeserializeOwned> Run<T> {
+
+    fn spilled(file: File, length: usize) -> Self {
+        let mut run = Run {
+            block: Vec::new(),
+            batches: Vec::new(),
+            file: Some(BufReader::new(file)),
+            remaining: length,
+        };
+        run.refill();
+        run
+    }
+
+    fn in_memory(mut batches: Vec<Vec<T>>) -> Self {
+        batches.reverse();
+        let mut run = Run {
+            block: Vec::new(),
+            batches,
+            file: None,
+            remaining: 0,
+        };
+        run.refill();
+        run
+    }
+
+    fn peek(&self) -> Option<&T> {
+        self.block.last()
+    }
+
+    /// Removes the next element, which must exist.
+    fn pop(&mut self) -> T {
+        let element = self.block.pop().expect("merged an exhausted run");
+        if self.block.is_empty() {
+            self.refill();
+        }
+        element
+    }
+
+    /// Loads the next block of elements, if any.
+    fn refill(&mut self) {
+        if let Some(ref mut reader) = self.file {
+            if self.remaining > 0 {
+                let count = ::std::cmp::min(self.remaining, BATCH_SIZE);
+                self.block.reserve(count);
+                for _ in 0 .. count {
+                    self.block.push(::bincode::deserialize_from(&mut *reader).expect("failed to read spill file"));
+                }
+                self.remaining -= count;
+            }
+        }
+        while self.block.is_empty() && !self.batches.is_empty() {
+            self.block = self.batches.pop().unwrap();
+        }
+        self.block.reverse();
+    }
+}
+
+mod test {
+
+    #[test]
+    fn test_external() {
+
+        use crate::{RadixSorter, RadixSorterBase};
+
+        let size = 1 << 20;
+        let mut vector = Vec::with_capacity(size);
+        let mut state = 0x2545F4914F6CDD1Du64;
+        for _ in 0 .. size {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            vector.push((state, vector.len()));
+        }
+
+        let mut sorter = super::Sorter::with_memory_limit(1 << 22);
+        for batch in vector.chunks(1000) {
+            sorter.push_batch(batch.to_vec(), &|x: &(u64, usize)| x.0);
+        }
+        for &element in vector.iter().take(1000) {
+            sorter.push(element, &|x: &(u64, usize)| x.0);
+        }
+        assert!(sorter.spilled_runs() > 1);
+
+        for &element in vector.clone().iter().take(1000) {
+            vector.push(element);
+        }
+        vector.sort();
+
+        let mut result = Vec::new();
+        sorter.finish_with(&|x: &(u64, usize)| x.0, |batch| result.extend(batch));
+        assert!(result.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        result.sort();
+
+        assert_eq!(result, vector);
+        assert_eq!(sorter.spilled_runs(), 0);
+
+        let mut empty = super::Sorter::<u64>::new();
+        assert!(RadixSorter::<u64, u64>::finish(&mut empty, &|&x| x).is_empty());
+    }
+
+    #[test]
+    fn test_external_padded() {
+
+        use crate::RadixSorter;
+
+        // elements with padding between their fields are spilled and read back intact.
+        let vector = (0 .. 10000u64).map(|x| ((x % 251) as u8, x.wrapping_mul(0x9E3779B97F4A7C15))).collect::<Vec<_>>();
+        let mut sorter = super::Sorter::with_memory_limit(1 << 12);
+        for &element in vector.iter() {
+            sorter.push(element, &|x: &(u8, u64)| x.1);
+        }
+        assert!(sorter.spilled_runs() > 1);
+
+        let mut result = Vec::new();
+        sorter.finish_with(&|x: &(u8, u64)| x.1, |batch| result.extend(batch));
+        let mut expected = vector.clone();
+        expected.sort_by_key(|x| x.1);
+        assert_eq!(result, expected);
+    }
+}
@@ -0,0 +1,248 @@
+//! Order-preserving radix keys, and multi-pass sorting by composite keys.
+//!
+//! A `RadixKey` maps a value to an unsigned integer whose order agrees with the value's order, so
+//! that signed integers, floats and the like can be radix sorted without hand-written mappings.
+//!
+//! A `CompositeKey` is a sequence of `u128` digits, most significant first, such as the fields of a
+//! tuple or the chunks of a byte string. `sort_composite` sorts by the first digit with an MSB radix
+//! sorter, and then sorts each group of elements with equal digits by the next digit, until the
+//! groups are singletons or their keys are exhausted.
+
+use std::mem::replace;
+
+use crate::{Unsigned, RadixSorter};
+use crate::msb::Sorter as MSBSorter;
+
+/// A type with an order-preserving map to an unsigned integer.
+pub trait RadixKey {
+    /// The unsigned integer the type maps to.
+    type Radix: Unsigned;
+    /// The unsigned integer for the value, ordered as the values are.
+    fn radix_key(&self) -> Self::Radix;
+}
+
+macro_rules! unsigned_radix_key {
+    ($($t:ty),*) => {$(
+        impl RadixKey for $t {
+            type Radix = $t;
+            #[inline] fn radix_key(&self) -> $t { *self }
+        }
+    )*}
+}
+
+unsigned_radix_key!(u8, u16, u32, u64, u128, usize);
+
+// signed integers are ordered as unsigned ones once their sign bit is flipped.
+macro_rules! signed_radix_key {
+    ($($t:ty => $u:ty),*) => {$(
+        impl RadixKey for $t {
+            type Radix = $u;
+            #[inline] fn radix_key(&self) -> $u { (*self as $u) ^ (1 << (8 * ::std::mem::size_of::<$u>() - 1)) }
+        }
+    )*}
+}
+
+signed_radix_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128, isize => usize);
+
+// floats are ordered as unsigned integers once negative values have all their bits flipped, and
+// others their sign bit. This is the IEEE 754 total order: `-0.0` precedes `0.0`, and NaNs with
+// the sign bit set precede all other values, and those without it follow them.
+impl RadixKey for f32 {
+    type Radix = u32;
+    #[inline]
+    fn radix_key(&self) -> u32 {
+        let bits = self.to_bits();
+        if bits >> 31 == 1 { !bits } else { bits | (1 << 31) }
+    }
+}
+
+impl RadixKey for f64 {
+    type Radix = u64;
+    #[inline]
+    fn radix_key(&self) -> u64 {
+        let bits = self.to_bits();
+        if bits >> 63 == 1 { !bits } else { bits | (1 << 63) }
+    }
+}
+
+impl RadixKey for char {
+    type Radix = u32;
+    #[inline] fn radix_key(&self) -> u32 { *self as u32 }
+}
+
+impl RadixKey for bool {
+    type Radix = u8;
+    #[inline] fn radix_key(&self) -> u8 { *self as u8 }
+}
+
+/// A key of one or more `u128` digits, compared digit by digit, most significant first.
+pub trait CompositeKey {
+    /// The digit at `pass`, and whether further digits follow it.
+    ///
+    /// Keys with equal digits up to `pass` must agree on whether further digits follow.
+    fn digit(&self, pass: usize) -> (u128, bool);
+}
+
+macro_rules! tuple_composite_key {
+    ($($name:ident $index:tt),*; $last:tt) => {
+        impl<$($name: RadixKey),*> CompositeKey for ($($name,)*) {
+            #[inline]
+            fn digit(&self, pass: usize) -> (u128, bool) {
+                match pass {
+                    $($index => (self.$index.radix_key().as_u128(), $index < $last),)*
+                    _ => panic!("composite key has no digit {}", pass),
+                }
+            }
+        }
+    }
+}
+
+tuple_composite_key!(A 0, B 1; 1);
+tuple_composite_key!(A 0, B 1, C 2; 2);
+tuple_composite_key!(A 0, B 1, C 2, D 3; 3);
+
+// byte strings are cut into chunks of fifteen bytes. The digit of a chunk holds its bytes, padded
+// with zeros, followed by a byte counting them, or sixteen if more chunks follow, so that a string
+// precedes its extensions.
+impl CompositeKey for [u8] {
+    #[inline]
+    fn digit(&self, pass: usize) -> (u128, bool) {
+        let start = ::std::cmp::min(15 * pass, self.len());
+        let chunk = &self[start .. ::std::cmp::min(start + 15, self.len())];
+        let mut digit = 0u128;
+        for (index, &byte) in chunk.iter().enumerate() {
+            digit |= (byte as u128) << (8 * (15 - index));
+        }
+        let more = self.len() > start + 15;
+        (digit | if more { 16 } else { chunk.len() as u128 }, more)
+    }
+}
+
+impl CompositeKey for str {
+    #[inline]
+    fn digit(&self, pass: usize) -> (u128, bool) {
+        self.as_bytes().digit(pass)
+    }
+}
+
+/// Sorts batched data by the composite key `key` returns, using `sorter` for each pass.
+///
+/// The sort is stable, and the sorted elements are left in `batches`.
+///
+/// # Examples
+/// ```
+/// use timely_sort::{MSBRadixSorter, RadixSorterBase, sort_composite};
+///
+/// let mut sorter = MSBRadixSorter::new();
+///
+/// let mut pairs = vec![vec![(3i64, 1u32), (-2, 7), (3, 0), (-2, 5)]];
+/// sort_composite(&mut sorter, &mut pairs, |x| x);
+/// assert_eq!(pairs.concat(), vec![(-2, 5), (-2, 7), (3, 0), (3, 1)]);
+///
+/// let mut sorter = MSBRadixSorter::new();
+/// let mut names = vec![vec!["timely", "dataflow", "time", "", "timely dataflow"]];
+/// sort_composite(&mut sorter, &mut names, |x| *x);
+/// assert_eq!(names.concat(), vec!["", "dataflow", "time", "timely", "timely dataflow"]);
+/// ```
+pub fn sort_composite<T, K, F>(sorter: &mut MSBSorter<T>, batches: &mut Vec<Vec<T>>, key: F)
+where
+    K: CompositeKey+?Sized,
+    F: Fn(&T)->&K,
+{
+    let source = replace(batches, Vec::new());
+    let mut sorted = Vec::new();
+    sort_pass(sorter, source, 0, &key, &mut sorted);
+    if !sorted.is_empty() {
+        batches.push(sorted);
+    }
+}
+
+/// Sorts `batches` by the digit at `pass`, and groups of equal digits by later digits, into `target`.
+fn sort_pass<T, K, F>(sorter: &mut MSBSorter<T>, mut batches: Vec<Vec<T>>, pass: usize, key: &F, target: &mut Vec<T>)
+where
+    K: CompositeKey+?Sized,
+    F: Fn(&T)->&K,
+{
+    sorter.sort(&mut batches, &|x| key(x).digit(pass).0);
+
+    let mut group = Vec::new();
+    let mut current = None;
+    for element in batches.into_iter().flat_map(|batch| batch.into_iter()) {
+        let digit = key(&element).digit(pass);
+        if current != Some(digit) {
+            finish_group(sorter, replace(&mut group, Vec::new()), current, pass, key, target);
+            current = Some(digit);
+        }
+        group.push(element);
+    }
+    finish_group(sorter, group, current, pass, key, target);
+}
+
+/// Moves a group of elements with equal digits up to `pass` into `target`, sorting it by later digits if needed.
+fn finish_group<T, K, F>(sorter: &mut MSBSorter<T>, group: Vec<T>, digit: Option<(u128, bool)>, pass: usize, key: &F, target: &mut Vec<T>)
+where
+    K: CompositeKey+?Sized,
+    F: Fn(&T)->&K,
+{
+    match digit {
+        Some((_, true)) if group.len() > 1 => sort_pass(sorter, vec![group], pass + 1, key, target),
+        _ => target.extend(group),
+    }
+}
+
+mod test {
+
+    #[test]
+    fn test_radix_keys() {
+
+        use crate::{RadixKey, MSBRadixSorter, RadixSorter, RadixSorterBase};
+
+        let mut integers = vec![vec![5i64, -1, i64::min_value(), 0, i64::max_value(), -300, 300]];
+        let mut expected = integers.concat();
+        expected.sort();
+        MSBRadixSorter::new().sort(&mut integers, &|x: &i64| x.radix_key());
+        assert_eq!(integers.concat(), expected);
+
+        let mut floats = vec![vec![2.5f64, -0.0, 0.0, -1.5, ::std::f64::INFINITY, ::std::f64::NEG_INFINITY, 1e-300, -1e300]];
+        MSBRadixSorter::new().sort(&mut floats, &|x: &f64| x.radix_key());
+        assert_eq!(floats.concat(), vec![::std::f64::NEG_INFINITY, -1e300, -1.5, -0.0, 0.0, 1e-300, 2.5, ::std::f64::INFINITY]);
+
+        let mut wide = vec![vec![1u128 << 100, 3, (1u128 << 100) - 1, u128::max_value(), 0]];
+        let mut expected = wide.concat();
+        expected.sort();
+        MSBRadixSorter::new().sort(&mut wide, &|x: &u128| *x);
+        assert_eq!(wide.concat(), expected);
+    }
+
+    #[test]
+    fn test_composite() {
+
+        use crate::{MSBRadixSorter, RadixSorterBase};
+        use super::sort_composite;
+
+        let mut strings = Vec::new();
+        let mut state = 12345u64;
+        for _ in 0 .. 10000 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let length = (state >> 59) as usize;
+            let prefix = if state & 1 == 0 { "a shared prefix longer than fifteen bytes " } else { "" };
+            let suffix = (0 .. length).map(|index| (b'a' + ((state >> (index * 2)) & 3) as u8) as char).collect::<String>();
+            strings.push(format!("{}{}", prefix, suffix));
+        }
+        let mut expected = strings.clone();
+        expected.sort();
+
+        let mut batches = vec![strings];
+        sort_composite(&mut MSBRadixSorter::new(), &mut batches, |x: &String| x.as_str());
+        assert_eq!(batches.concat(), expected);
+
+        let mut triples = (0 .. 10000u64).map(|x| ((x % 7) as i8 - 3, (x % 13) as f32 - 6.5, x)).collect::<Vec<_>>();
+        triples.reverse();
+        let mut expected = triples.clone();
+        expected.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+        let mut batches = vec![triples];
+        sort_composite(&mut MSBRadixSorter::new(), &mut batches, |x| x);
+        assert_eq!(batches.concat(), expected);
+    }
+}
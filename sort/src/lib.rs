@@ -5,13 +5,21 @@
 //! * LSBRadixSorter: A least-significant byte radix sorter.
 //! * MSBRadixSorter: A most-significant byte radix sorter.
 //! * LSBSWCRadixSorter: A least-significant byte radix sorter with software write combining.
+//! * ParallelMSBRadixSorter: A most-significant byte radix sorter that sorts buckets on multiple threads.
+//! * ExternalRadixSorter: A most-significant byte radix sorter that spills sorted runs to disk.
 //!
 //! There should probably be a `MSBSWCRadixSorter` in the future, because I like both of those things.
+//!
+//! Keys of signed integers, floats and other types are mapped to unsigned integers in an order-preserving
+//! way by `RadixKey`, and tuples and byte strings are sorted one digit per pass with `sort_composite`.
 
+mod external;
+mod keys;
 mod lsb;
 mod lsb_swc;
 mod msb;
 mod msb_swc;
+mod msb_parallel;
 mod stash;
 mod batched_vec;
 mod swc_buffer;
@@ -20,12 +28,19 @@ pub use lsb::Sorter as LSBRadixSorter;
 pub use lsb_swc::Sorter as LSBSWCRadixSorter;
 pub use msb::Sorter as MSBRadixSorter;
 pub use msb_swc::Sorter as MSBSWCRadixSorter;
+pub use msb_parallel::Sorter as ParallelMSBRadixSorter;
+pub use external::Sorter as ExternalRadixSorter;
 pub use swc_buffer::SWCBuffer;
+pub use keys::{RadixKey, CompositeKey, sort_composite};
 
 /// An unsigned integer fit for use as a radix key.
 pub trait Unsigned : Ord {
     fn bytes() -> usize;
     fn as_u64(&self) -> u64;
+    /// The byte at `index`, counting from the least significant byte.
+    #[inline] fn byte(&self, index: usize) -> u8 { (self.as_u64() >> (8 * index)) as u8 }
+    /// The integer widened to `u128`.
+    #[inline] fn as_u128(&self) -> u128 { self.as_u64() as u128 }
 }
 
 impl Unsigned for  u8 { #[inline]fn bytes() -> usize { 1 } #[inline] fn as_u64(&self) -> u64 { *self as u64 } }
@@ -33,6 +48,13 @@ impl Unsigned for u16 { #[inline]fn bytes() -> usize { 2 } #[inline] fn as_u64(&
 impl Unsigned for u32 { #[inline]fn bytes() -> usize { 4 } #[inline] fn as_u64(&self) -> u64 { *self as u64 } }
 impl Unsigned for u64 { #[inline]fn bytes() -> usize { 8 } #[inline] fn as_u64(&self) -> u64 { *self as u64 } }
 impl Unsigned for usize { #[inline]fn bytes() -> usize { ::std::mem::size_of::<usize>() } #[inline]fn as_u64(&self) -> u64 { *self as u64 } }
+impl Unsigned for u128 {
+    #[inline] fn bytes() -> usize { 16 }
+    /// The least significant 64 bits of the integer.
+    #[inline] fn as_u64(&self) -> u64 { *self as u64 }
+    #[inline] fn byte(&self, index: usize) -> u8 { (*self >> (8 * index)) as u8 }
+    #[inline] fn as_u128(&self) -> u128 { *self }
+}
 
 /// Functionality provided by a radix sorter.
 ///
@@ -23,18 +23,18 @@ impl<T, U: Unsigned> RadixSorter<T, U> for Sorter<T> {
 
     #[inline]
     fn push<F: Fn(&T)->U>(&mut self, element: T, function: &F) {
-        self.shuffler.push(element, &|x| (function(x).as_u64() % 256) as u8);
+        self.shuffler.push(element, &|x| function(x).byte(0));
     }
 
     #[inline]
     fn push_batch<F: Fn(&T)->U>(&mut self, batch: Vec<T>, function: &F) {
-        self.shuffler.push_batch(batch,  &|x| (function(x).as_u64() % 256) as u8);
+        self.shuffler.push_batch(batch,  &|x| function(x).byte(0));
     }
 
     fn finish_into<F: Fn(&T)->U>(&mut self, target: &mut Vec<Vec<T>>, function: &F) {
         self.shuffler.finish_into(target);
         for byte in 1..(<U as Unsigned>::bytes()) {
-            self.reshuffle(target, &|x| ((function(x).as_u64() >> (8 * byte)) % 256) as u8);
+            self.reshuffle(target, &|x| function(x).byte(byte));
         }
     }
 
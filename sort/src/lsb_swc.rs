@@ -26,18 +26,18 @@ impl<T, U: Unsigned> RadixSorter<T, U> for Sorter<T> {
 
     #[inline]
     fn push<F: Fn(&T)->U>(&mut self, element: T, key: &F) {
-        self.shuffler.push(element, &|x| (key(x).as_u64() % 256) as u8);
+        self.shuffler.push(element, &|x| key(x).byte(0));
     }
 
     #[inline]
     fn push_batch<F: Fn(&T)->U>(&mut self, batch: Vec<T>, key: &F) {
-        self.shuffler.push_batch(batch,  &|x| (key(x).as_u64() % 256) as u8);
+        self.shuffler.push_batch(batch,  &|x| key(x).byte(0));
     }
 
     fn finish_into<F: Fn(&T)->U>(&mut self, target: &mut Vec<Vec<T>>, key: &F) {
         self.shuffler.finish_into(target);
         for byte in 1..(<U as Unsigned>::bytes()) {
-            self.reshuffle(target, &|x| ((key(x).as_u64() >> (8 * byte)) % 256) as u8);
+            self.reshuffle(target, &|x| key(x).byte(byte));
         }
     }
 }
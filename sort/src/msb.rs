@@ -53,7 +53,7 @@ impl<T, U: Unsigned> RadixSorter<T, U> for Sorter<T> {
     #[inline]
     fn push<F: Fn(&T)->U>(&mut self, element: T, bytes: &F) {
         let depth = U::bytes() - 1;
-        let byte = ((bytes(&element).as_u64() >> (8 * depth)) & 0xFF) as usize;
+        let byte = bytes(&element).byte(depth) as usize;
         self.buckets.get_mut(byte).push(element, &mut self.stash);
     }
 
@@ -145,7 +145,7 @@ impl<T> Sorter<T> {
                 // push all of source into lists and tails.
                 for mut batch in source.drain(..) {
                     for element in batch.drain(..) {
-                        let byte = ((bytes(&element).as_u64() >> (8 * depth)) & 0xFF) as usize;
+                        let byte = bytes(&element).byte(depth) as usize;
                         self.buckets.get_mut(byte).push(element, &mut self.stash);
                     }
                     self.stash.give(batch);
@@ -0,0 +1,244 @@
+//! Parallel most-significant byte (MSB) radix sorting.
+//!
+//! Elements are partitioned by the most significant byte of their key as they are pushed. When the
+//! sorting session finishes, buckets holding more than an even share of the elements are split by
+//! the next byte of their keys, and so on, so that keys sharing their leading bytes do not all land
+//! in one bucket. The parts are dealt out to a number of scoped threads, largest first, and each
+//! thread sorts its parts with an MSB sorter of its own. The sorted parts are then reassembled in
+//! order on the calling thread.
+//!
+//! The `RadixSorter` trait does not require key functions to be `Sync`, so the key of each element
+//! is computed on the calling thread and sent to the sorting threads along with the element. Small
+//! sessions, below `PARALLEL_THRESHOLD` elements, are sorted on the calling thread instead.
+
+use std::mem::replace;
+
+use crossbeam_utils::thread;
+
+use crate::{Unsigned, RadixSorter, RadixSorterBase};
+use crate::stash::Stash;
+use crate::batched_vec::BatchedVecX256;
+use crate::msb::Sorter as MSBSorter;
+
+macro_rules! per_cache_line {
+    ($t:ty) => {{ ::std::cmp::max(64 / ::std::mem::size_of::<$t>(), 4) }}
+}
+
+macro_rules! lines_per_page {
+    () => {{ 2 * 4096 / 64 }}
+}
+
+/// The number of threads used by a sorter, unless otherwise specified.
+const DEFAULT_THREADS: usize = 4;
+
+/// The number of elements below which a session is sorted on the calling thread.
+const PARALLEL_THRESHOLD: usize = 1 << 16;
+
+/// A "most-significant byte" (MSB) radix sorter that sorts on multiple threads.
+///
+/// The sorter implements `RadixSorter` for elements and keys that can be sent between threads, as
+/// all elements with unsigned integer keys can, so that it can replace an `MSBRadixSorter` without
+/// changes to the calling code.
+pub struct Sorter<T> {
+    threads: usize,
+    buckets: BatchedVecX256<T>,         // elements by the most significant byte of their key.
+    elements: usize,                    // the number of elements in `buckets`.
+    sorter: MSBSorter<T>,               // sorts buckets on the calling thread.
+    stash: Stash<T>,                    // empty buffers we might be able to use.
+}
+
+impl<T> Sorter<T> {
+    /// Allocates a sorter that sorts on `threads` threads.
+    pub fn with_threads(threads: usize) -> Self {
+        assert!(threads > 0, "a sorter requires at least one thread");
+        Sorter {
+            threads,
+            buckets: BatchedVecX256::new(),
+            elements: 0,
+            sorter: MSBSorter::new(),
+            stash: Stash::new(lines_per_page!() * per_cache_line!(T)),
+        }
+    }
+}
+
+impl<T: Send, U: Unsigned+Send+Copy> RadixSorter<T, U> for Sorter<T> {
+
+    #[inline]
+    fn push<F: Fn(&T)->U>(&mut self, element: T, key: &F) {
+        let byte = key(&element).byte(U::bytes() - 1) as usize;
+        self.buckets.get_mut(byte).push(element, &mut self.stash);
+        self.elements += 1;
+    }
+
+    #[inline]
+    fn push_batch<F: Fn(&T)->U>(&mut self, mut batch: Vec<T>, key: &F) {
+        for element in batch.drain(..) {
+            self.push(element, key);
+        }
+        self.stash.give(batch);
+    }
+
+    fn finish_into<F: Fn(&T)->U>(&mut self, target: &mut Vec<Vec<T>>, key: &F) {
+
+        let mut buckets = Vec::new();
+        for byte in 0 .. 256 {
+            let mut bucket = self.buckets.get_mut(byte);
+            if !bucket.is_empty() {
+                buckets.push((byte as u8, bucket.finish()));
+            }
+        }
+
+        let elements = replace(&mut self.elements, 0);
+        if self.threads == 1 || elements < PARALLEL_THRESHOLD {
+            for (_, mut bucket) in buckets {
+                self.sorter.sort(&mut bucket, key);
+                target.extend(bucket);
+            }
+            return;
+        }
+
+        // pair elements with their keys, and split buckets larger than an even share of the elements.
+        let limit = ::std::cmp::max(elements / self.threads, 1);
+        let mut parts = Vec::new();
+        for (byte, bucket) in buckets {
+            let mut pairs = Vec::with_capacity(bucket.iter().map(|batch| batch.len()).sum());
+            for mut batch in bucket {
+                pairs.extend(batch.drain(..).map(|element| (key(&element), element)));
+                self.stash.give(batch);
+            }
+            split(vec![byte], pairs, U::bytes() - 1, limit, &mut parts);
+        }
+
+        let mut sorted = thread::scope(|scope| {
+            let handles = deal(parts, self.threads)
+                .into_iter()
+                .filter(|share| !share.1.is_empty())
+                .map(|(_, mut share)| scope.spawn(move |_| {
+                    let mut sorter = MSBSorter::new();
+                    for (_, pairs) in share.iter_mut() {
+                        sorter.sort(pairs, &|pair: &(U, T)| pair.0);
+                    }
+                    share
+                }))
+                .collect::<Vec<_>>();
+            handles.into_iter().flat_map(|handle| handle.join().expect("sorting thread panicked")).collect::<Vec<_>>()
+        }).expect("sorting thread panicked");
+
+        // parts are ordered by the leading bytes of their keys, none of which extend those of another part.
+        sorted.sort_by(|x, y| x.0.cmp(&y.0));
+        for (_, pairs) in sorted {
+            for mut batch in pairs {
+                let mut elements = self.stash.get();
+                elements.extend(batch.drain(..).map(|(_, element)| element));
+                target.push(elements);
+            }
+        }
+    }
+}
+
+/// The leading bytes shared by the keys of a part, and its batches of key-element pairs.
+type Part<U, T> = (Vec<u8>, Vec<Vec<(U, T)>>);
+
+/// Adds `pairs`, whose keys start with the bytes of `prefix`, to `parts`, splitting them by the byte
+/// below `byte` until no part holds more than `limit` pairs or the bytes of the keys are exhausted.
+fn split<T, U: Unsigned+Copy>(prefix: Vec<u8>, pairs: Vec<(U, T)>, byte: usize, limit: usize, parts: &mut Vec<Part<U, T>>) {
+    if pairs.len() <= limit || byte == 0 {
+        parts.push((prefix, vec![pairs]));
+    }
+    else {
+        let mut split_pairs = (0 .. 256).map(|_| Vec::new()).collect::<Vec<_>>();
+        for pair in pairs {
+            split_pairs[pair.0.byte(byte - 1) as usize].push(pair);
+        }
+        for (value, pairs) in split_pairs.into_iter().enumerate() {
+            if !pairs.is_empty() {
+                let mut prefix = prefix.clone();
+                prefix.push(value as u8);
+                split(prefix, pairs, byte - 1, limit, parts);
+            }
+        }
+    }
+}
+
+/// Deals `parts` to `threads` shares, largest first, each to the share with the fewest pairs so far.
+fn deal<T, U>(parts: Vec<Part<U, T>>, threads: usize) -> Vec<(usize, Vec<Part<U, T>>)> {
+    let mut counted = parts.into_iter().map(|part| (part.1.iter().map(|batch| batch.len()).sum::<usize>(), part)).collect::<Vec<_>>();
+    counted.sort_by_key(|&(count, _)| ::std::cmp::Reverse(count));
+
+    let mut shares = (0 .. threads).map(|_| (0, Vec::new())).collect::<Vec<_>>();
+    for (count, part) in counted {
+        let share = shares.iter_mut().min_by_key(|share| share.0).unwrap();
+        share.0 += count;
+        share.1.push(part);
+    }
+    shares
+}
+
+impl<T> RadixSorterBase<T> for Sorter<T> {
+    fn new() -> Self {
+        Sorter::with_threads(DEFAULT_THREADS)
+    }
+    fn rebalance(&mut self, buffers: &mut Vec<Vec<T>>, intended: usize) {
+        self.stash.rebalance(buffers, intended);
+    }
+}
+
+mod test {
+
+    #[test]
+    fn test_parallel() {
+
+        use crate::RadixSorter;
+
+        for &size in &[1 << 10, 1 << 20] {
+
+            let mut vector = Vec::with_capacity(size);
+            let mut state = 0x9E3779B97F4A7C15u64;
+            for index in 0 .. size {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                vector.push((state >> (index % 48), index.to_string()));
+            }
+
+            let mut sorter = super::Sorter::with_threads(3);
+            for element in vector.iter().cloned() {
+                sorter.push(element, &|x: &(u64, String)| x.0);
+            }
+
+            let mut result = Vec::new();
+            for batch in sorter.finish(&|x: &(u64, String)| x.0) {
+                result.extend(batch);
+            }
+
+            assert!(result.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+            result.sort();
+            vector.sort();
+            assert_eq!(result, vector);
+        }
+    }
+
+    #[test]
+    fn test_shared_top_byte() {
+
+        use crate::RadixSorter;
+
+        // all keys share their top byte, and should still be spread evenly across threads.
+        let size = 1 << 18;
+        let pairs = (0 .. size as u64).rev().map(|key| (key, ())).collect::<Vec<_>>();
+        let mut parts = Vec::new();
+        super::split(vec![0], pairs, 7, size / 4, &mut parts);
+        let shares = super::deal(parts, 4);
+        assert!(shares.iter().all(|share| share.0 == size / 4));
+
+        let mut sorter = super::Sorter::with_threads(4);
+        for key in (0 .. size as u64).rev() {
+            sorter.push(key, &|&x: &u64| x);
+        }
+        let mut result = Vec::new();
+        for batch in sorter.finish(&|&x: &u64| x) {
+            result.extend(batch);
+        }
+        assert_eq!(result, (0 .. size as u64).collect::<Vec<_>>());
+    }
+}
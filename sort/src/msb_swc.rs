@@ -65,7 +65,7 @@ impl<T, U: Unsigned> RadixSorter<T, U> for Sorter<T> {
     #[inline]
     fn push<F: Fn(&T)->U>(&mut self, element: T, bytes: &F) {
         let depth = U::bytes() - 1;
-        let byte = ((bytes(&element).as_u64() >> (8 * depth)) & 0xFF) as usize;
+        let byte = bytes(&element).byte(depth) as usize;
 
         // write the element to our scratch buffer space and consider it taken care of.
         if self.buffer.full(byte) {
@@ -188,7 +188,7 @@ impl<T> Sorter<T> {
             // push all of source into lists and tails.
             for mut batch in source.drain(..) {
                 for element in batch.drain(..) {
-                    let byte = ((bytes(&element).as_u64() >> (8 * depth)) & 0xFF) as usize;
+                    let byte = bytes(&element).byte(depth) as usize;
                     if self.buffer.full(byte) {
                         self.buffer.drain_into(byte, &mut self.buckets.get_mut(byte), &mut self.stash);
                     }
@@ -5,6 +5,7 @@ pub use in_memory::InMemoryBackend;
 pub use in_memory_native::InMemoryNativeBackend;
 pub use self::rocksdb::RocksDBBackend;
 pub use rocksdbmerge::RocksDBMergeBackend;
+pub use spilling::SpillingBackend;
 
 mod faster;
 mod faster_in_memory;
@@ -13,3 +14,4 @@ mod in_memory;
 mod in_memory_native;
 mod rocksdb;
 mod rocksdbmerge;
+mod spilling;
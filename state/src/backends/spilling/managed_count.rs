@@ -0,0 +1,34 @@
+use crate::primitives::{ManagedCount, ManagedMap};
+
+// The key under which the count is held in its map.
+const COUNT_KEY: u64 = 0;
+
+// A count held as the single entry of a spilling map, so that it is charged to the budget and
+// spilled to disk like any map entry.
+pub struct SpillingManagedCount {
+    map: Box<ManagedMap<u64, i64>>,
+}
+
+impl SpillingManagedCount {
+    pub fn new(map: Box<ManagedMap<u64, i64>>) -> Self {
+        SpillingManagedCount { map }
+    }
+}
+
+impl ManagedCount for SpillingManagedCount {
+    fn decrease(&mut self, amount: i64) {
+        self.map.rmw(COUNT_KEY, -amount);
+    }
+
+    fn increase(&mut self, amount: i64) {
+        self.map.rmw(COUNT_KEY, amount);
+    }
+
+    fn get(&self) -> i64 {
+        self.map.get(&COUNT_KEY).map_or(0, |count| *count)
+    }
+
+    fn set(&mut self, value: i64) {
+        self.map.insert(COUNT_KEY, value);
+    }
+}
@@ -0,0 +1,384 @@
+use crate::backends::spilling::MemoryBudget;
+use crate::primitives::ManagedMap;
+use bincode::{deserialize, serialize, serialized_size};
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::{Rc, Weak};
+
+struct HotEntry<V> {
+    value: Rc<V>,
+    last_access: u64,
+    size: usize,
+}
+
+// The entries of a map, shared by all handles to the map obtained from one backend.
+pub struct Tiers<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    // Identifies the map in the recency order of the budget.
+    id: usize,
+    hot: HashMap<K, HotEntry<V>>,
+    cold: Box<ManagedMap<K, V>>,
+}
+
+impl<K, V> Tiers<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    // Creates the tiers of a map over `cold`, registered with `budget` so that the entries of
+    // the map can be spilled to make room for those of any map sharing the budget.
+    pub fn register(
+        budget: &Rc<RefCell<MemoryBudget>>,
+        cold: Box<ManagedMap<K, V>>,
+    ) -> Rc<RefCell<Self>> {
+        let mut budget = budget.borrow_mut();
+        budget.registered += 1;
+        let id = budget.registered;
+        let tiers = Rc::new(RefCell::new(Tiers {
+            id,
+            hot: HashMap::new(),
+            cold,
+        }));
+        let spill: Rc<Spill> = tiers.clone();
+        budget.maps.insert(id, Rc::downgrade(&spill));
+        tiers
+    }
+}
+
+// A map whose entries can be moved from memory to its cold tier.
+pub trait Spill {
+    // Moves the entry with the serialised key `key` to the cold tier, if it is in memory.
+    fn spill(&self, key: &[u8], budget: &RefCell<MemoryBudget>);
+}
+
+impl<K, V> Spill for RefCell<Tiers<K, V>>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    fn spill(&self, key: &[u8], budget: &RefCell<MemoryBudget>) {
+        let key: K = deserialize(key).unwrap();
+        let tiers = &mut *self.borrow_mut();
+        if let Some(entry) = tiers.hot.remove(&key) {
+            budget.borrow_mut().used -= entry.size;
+            tiers.cold.insert(key, unwrap_or_copy(entry.value));
+        }
+    }
+}
+
+// Moves the least recently used entries of all maps sharing the budget to their cold tiers,
+// until the budget is respected or no entries are left in memory.
+fn spill(budget: &RefCell<MemoryBudget>) {
+    loop {
+        let (map, key) = {
+            let budget = &mut *budget.borrow_mut();
+            if !budget.exceeded() {
+                return;
+            }
+            let coldest = match budget.recency.keys().next() {
+                None => return,
+                Some(tick) => *tick,
+            };
+            let (id, key) = budget.recency.remove(&coldest).unwrap();
+            (budget.maps.get(&id).and_then(Weak::upgrade), key)
+        };
+        if let Some(map) = map {
+            map.spill(&key, budget);
+        }
+    }
+}
+
+pub struct SpillingManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    budget: Rc<RefCell<MemoryBudget>>,
+    tiers: Rc<RefCell<Tiers<K, V>>>,
+}
+
+impl<K, V> SpillingManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    pub fn new(budget: Rc<RefCell<MemoryBudget>>, tiers: Rc<RefCell<Tiers<K, V>>>) -> Self {
+        SpillingManagedMap { budget, tiers }
+    }
+
+    fn touch(&self, key: &K) -> Option<Rc<V>> {
+        let tiers = &mut *self.tiers.borrow_mut();
+        let entry = tiers.hot.get_mut(key)?;
+        let budget = &mut *self.budget.borrow_mut();
+        let tick = budget.tick();
+        let accessed = budget
+            .recency
+            .remove(&entry.last_access)
+            .expect("Hot entry missing from recency order");
+        budget.recency.insert(tick, accessed);
+        entry.last_access = tick;
+        Some(Rc::clone(&entry.value))
+    }
+
+    fn insert_hot(&self, key: K, value: Rc<V>) {
+        let serialised_key = serialize(&key).unwrap();
+        let size = serialised_key.len() + serialized_size(&*value).unwrap() as usize;
+        {
+            let tiers = &mut *self.tiers.borrow_mut();
+            let budget = &mut *self.budget.borrow_mut();
+            let tick = budget.tick();
+            let previous = tiers.hot.insert(
+                key,
+                HotEntry {
+                    value,
+                    last_access: tick,
+                    size,
+                },
+            );
+            if let Some(previous) = previous {
+                budget.recency.remove(&previous.last_access);
+                budget.used -= previous.size;
+            }
+            budget.recency.insert(tick, (tiers.id, serialised_key));
+            budget.used += size;
+        }
+        spill(&self.budget);
+    }
+
+    fn remove_hot(&self, key: &K) -> Option<Rc<V>> {
+        let entry = self.tiers.borrow_mut().hot.remove(key)?;
+        let mut budget = self.budget.borrow_mut();
+        budget.recency.remove(&entry.last_access);
+        budget.used -= entry.size;
+        Some(entry.value)
+    }
+}
+
+// Takes the value out of an entry, or a deserialised copy of it if readers still hold the entry.
+fn unwrap_or_copy<V: FasterValue>(value: Rc<V>) -> V {
+    Rc::try_unwrap(value).unwrap_or_else(|shared| deserialize(&serialize(&*shared).unwrap()).unwrap())
+}
+
+impl<K, V> ManagedMap<K, V> for SpillingManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    // Each entry is held by exactly one tier, so an entry inserted in memory replaces any on disk.
+    fn insert(&mut self, key: K, value: V) {
+        {
+            let tiers = &mut *self.tiers.borrow_mut();
+            if !tiers.hot.contains_key(&key) {
+                tiers.cold.remove(&key);
+            }
+        }
+        self.insert_hot(key, Rc::new(value));
+    }
+
+    fn get(&self, key: &K) -> Option<Rc<V>> {
+        if let Some(value) = self.touch(key) {
+            return Some(value);
+        }
+        let value = Rc::new(self.tiers.borrow_mut().cold.remove(key)?);
+        // Keys are not `Clone`, so the promoted entry gets a deserialised copy of the key.
+        let owned_key = deserialize(&serialize(key).unwrap()).unwrap();
+        self.insert_hot(owned_key, Rc::clone(&value));
+        Some(value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        match self.remove_hot(key) {
+            None => self.tiers.borrow_mut().cold.remove(key),
+            Some(value) => Some(unwrap_or_copy(value)),
+        }
+    }
+
+    fn rmw(&mut self, key: K, modification: V) {
+        let new_value = match self.get(&key) {
+            None => modification,
+            Some(value) => value.rmw(modification),
+        };
+        self.insert(key, new_value);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        let tiers = self.tiers.borrow();
+        tiers.hot.contains_key(key) || tiers.cold.contains(key)
+    }
+}
+
+impl<K, V> Drop for SpillingManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    // Hands everything still in memory to the cold tier once the last handle to the map is
+    // dropped, so that the contents survive it.
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.tiers) > 1 {
+            return;
+        }
+        let tiers = &mut *self.tiers.borrow_mut();
+        let budget = &mut *self.budget.borrow_mut();
+        budget.maps.remove(&tiers.id);
+        for (key, entry) in tiers.hot.drain() {
+            budget.recency.remove(&entry.last_access);
+            budget.used -= entry.size;
+            tiers.cold.insert(key, unwrap_or_copy(entry.value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SpillingManagedMap, Tiers};
+    use crate::backends::spilling::MemoryBudget;
+    use crate::backends::InMemoryBackend;
+    use crate::primitives::ManagedMap;
+    use crate::StateBackend;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn spilling_map(
+        budget: &Rc<RefCell<MemoryBudget>>,
+        disk: &InMemoryBackend,
+    ) -> SpillingManagedMap<u64, u64> {
+        spilling_map_named(budget, disk, "map")
+    }
+
+    fn spilling_map_named(
+        budget: &Rc<RefCell<MemoryBudget>>,
+        disk: &InMemoryBackend,
+        name: &str,
+    ) -> SpillingManagedMap<u64, u64> {
+        let tiers = Tiers::register(budget, disk.get_managed_map(name));
+        SpillingManagedMap::new(Rc::clone(budget), tiers)
+    }
+
+    #[test]
+    fn map_stays_within_budget() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(64)));
+        let disk = InMemoryBackend::new();
+        let mut map = spilling_map(&budget, &disk);
+
+        for key in 0..100 {
+            map.insert(key, key * 10);
+            assert!(budget.borrow().used <= 64);
+        }
+        for key in 0..100 {
+            assert_eq!(map.get(&key), Some(Rc::new(key * 10)));
+        }
+    }
+
+    #[test]
+    fn maps_spill_least_recently_used_entries_of_any_map() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(48)));
+        let disk = InMemoryBackend::new();
+        let mut large = spilling_map_named(&budget, &disk, "large");
+        let mut small = spilling_map_named(&budget, &disk, "small");
+
+        large.insert(1, 1);
+        large.insert(2, 2);
+        small.insert(1, 1);
+        small.insert(2, 2);
+        assert!(budget.borrow().used <= 48);
+        assert!(!large.tiers.borrow().hot.contains_key(&1));
+        assert!(large.tiers.borrow().hot.contains_key(&2));
+        assert!(small.tiers.borrow().hot.contains_key(&1));
+        assert!(small.tiers.borrow().hot.contains_key(&2));
+        assert_eq!(large.get(&1), Some(Rc::new(1)));
+    }
+
+    #[test]
+    fn map_rmw_reads_spilled_values() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(16)));
+        let disk = InMemoryBackend::new();
+        let mut map = spilling_map(&budget, &disk);
+
+        map.insert(1, 32);
+        map.insert(2, 0);
+        map.insert(3, 0);
+        map.rmw(1, 10);
+        assert_eq!(map.get(&1), Some(Rc::new(42)));
+    }
+
+    #[test]
+    fn map_remove_clears_both_tiers() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(16)));
+        let disk = InMemoryBackend::new();
+        let mut map = spilling_map(&budget, &disk);
+
+        map.insert(1, 1);
+        map.insert(2, 2);
+        map.insert(3, 3);
+        assert_eq!(map.remove(&1), Some(1));
+        assert!(!map.contains(&1));
+        assert_eq!(map.get(&1), None);
+    }
+
+    #[test]
+    fn map_promotes_entries_out_of_the_cold_tier() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(32)));
+        let disk = InMemoryBackend::new();
+        let mut map = spilling_map(&budget, &disk);
+        let cold = disk.get_managed_map::<u64, u64>("map");
+
+        map.insert(1, 1);
+        map.insert(2, 2);
+        map.insert(3, 3);
+        assert!(cold.contains(&1));
+        assert_eq!(map.get(&1), Some(Rc::new(1)));
+        assert!(!cold.contains(&1));
+        assert!(cold.contains(&2));
+
+        map.insert(2, 20);
+        assert!(!cold.contains(&2));
+        assert_eq!(map.remove(&2), Some(20));
+        assert_eq!(map.get(&2), None);
+    }
+
+    #[test]
+    fn map_remove_returns_shared_values() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(1024)));
+        let disk = InMemoryBackend::new();
+        let mut map = spilling_map(&budget, &disk);
+
+        map.insert(1, 7);
+        let held = map.get(&1);
+        assert_eq!(map.remove(&1), Some(7));
+        assert_eq!(held, Some(Rc::new(7)));
+        assert!(!map.contains(&1));
+    }
+
+    #[test]
+    fn map_drop_hands_contents_to_disk() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(1024)));
+        let disk = InMemoryBackend::new();
+        {
+            let mut map = spilling_map(&budget, &disk);
+            map.insert(1, 100);
+        }
+        assert_eq!(budget.borrow().used, 0);
+        let map = disk.get_managed_map::<u64, u64>("map");
+        assert_eq!(map.get(&1), Some(Rc::new(100)));
+    }
+
+    #[test]
+    fn map_drop_hands_shared_values_to_disk() {
+        let budget = Rc::new(RefCell::new(MemoryBudget::new(1024)));
+        let disk = InMemoryBackend::new();
+        let held = {
+            let mut map = spilling_map(&budget, &disk);
+            map.insert(1, 100);
+            map.get(&1)
+        };
+        assert_eq!(held, Some(Rc::new(100)));
+        let map = disk.get_managed_map::<u64, u64>("map");
+        assert_eq!(map.get(&1), Some(Rc::new(100)));
+    }
+}
@@ -0,0 +1,36 @@
+use crate::primitives::{ManagedMap, ManagedValue};
+use faster_rs::{FasterRmw, FasterValue};
+use std::rc::Rc;
+
+// The key under which the value is held in its map.
+const VALUE_KEY: u64 = 0;
+
+// A value held as the single entry of a spilling map, so that it is charged to the budget and
+// spilled to disk like any map entry.
+pub struct SpillingManagedValue<V: 'static + FasterValue + FasterRmw> {
+    map: Box<ManagedMap<u64, V>>,
+}
+
+impl<V: 'static + FasterValue + FasterRmw> SpillingManagedValue<V> {
+    pub fn new(map: Box<ManagedMap<u64, V>>) -> Self {
+        SpillingManagedValue { map }
+    }
+}
+
+impl<V: 'static + FasterValue + FasterRmw> ManagedValue<V> for SpillingManagedValue<V> {
+    fn set(&mut self, value: V) {
+        self.map.insert(VALUE_KEY, value);
+    }
+
+    fn get(&self) -> Option<Rc<V>> {
+        self.map.get(&VALUE_KEY)
+    }
+
+    fn take(&mut self) -> Option<V> {
+        self.map.remove(&VALUE_KEY)
+    }
+
+    fn rmw(&mut self, modification: V) {
+        self.map.rmw(VALUE_KEY, modification);
+    }
+}
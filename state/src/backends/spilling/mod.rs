@@ -0,0 +1,212 @@
+//! A backend holding managed state in memory up to a byte budget, spilling the least recently
+//! used map entries to a disk backend `D` once the budget is exceeded. Values and counts are held
+//! as single-entry maps, so that they are charged to the budget and spilled like map entries. Entries are ordered by
+//! their last access across all maps of the backend, so that inserting into one map may spill
+//! the entries of another.
+//!
+//! The budget is shared by every primitive obtained from one backend instance. Dataflows build
+//! one backend per dataflow, and the `_core` operator constructors one backend per operator, so
+//! the same type gives a per-dataflow or a per-operator quota depending on where it is used.
+//! The budget is set with `Worker::dataflow_with_memory_budget` for a dataflow, or with
+//! `StateHandle::set_operator_memory_budget` for an operator, and otherwise read from the
+//! `TIMELY_STATE_MEMORY_BUDGET` environment variable by `StateBackend::new`. Backends are built by
+//! each worker, so the budget applies per worker: a process running `n` workers may hold up to
+//! `n` times the budget in memory for each dataflow.
+//!
+//! Handles to a map of the same name share its in-memory entries, as they share its entries on
+//! disk, so that an entry is held in memory at most once and is read from memory by every handle.
+
+use managed_count::SpillingManagedCount;
+use managed_map::{Spill, SpillingManagedMap, Tiers};
+use managed_value::SpillingManagedValue;
+
+mod managed_count;
+mod managed_map;
+mod managed_value;
+
+use crate::primitives::{ManagedCount, ManagedMap, ManagedValue};
+use crate::StateBackend;
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::rc::{Rc, Weak};
+
+const DEFAULT_MEMORY_BUDGET: usize = 256 * 1024 * 1024; // 256 MB
+
+pub struct MemoryBudget {
+    limit: usize,
+    used: usize,
+    clock: u64,
+    // Entries held in memory by the maps of the backend, by their last access, as the identifier
+    // of their map and their serialised key.
+    recency: BTreeMap<u64, (usize, Vec<u8>)>,
+    // The maps of the backend with live handles, by identifier.
+    maps: HashMap<usize, Weak<Spill>>,
+    registered: usize,
+}
+
+impl MemoryBudget {
+    fn new(limit: usize) -> Self {
+        MemoryBudget {
+            limit,
+            used: 0,
+            clock: 0,
+            recency: BTreeMap::new(),
+            maps: HashMap::new(),
+            registered: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn exceeded(&self) -> bool {
+        self.used > self.limit
+    }
+}
+
+pub struct SpillingBackend<D: StateBackend> {
+    budget: Rc<RefCell<MemoryBudget>>,
+    disk: D,
+    // The entries of maps with live handles, by name.
+    maps: RefCell<HashMap<String, Weak<Any>>>,
+}
+
+impl<D: StateBackend> SpillingBackend<D> {
+    /// The number of bytes currently held in memory by primitives of this backend.
+    pub fn memory_used(&self) -> usize {
+        self.budget.borrow().used
+    }
+}
+
+impl<D: StateBackend> StateBackend for SpillingBackend<D> {
+    /// Uses the budget given by the `TIMELY_STATE_MEMORY_BUDGET` environment variable (in bytes),
+    /// or 256 MB if it is not set. Each worker builds its own backends, each with this budget.
+    fn new() -> Self {
+        let budget = ::std::env::var("TIMELY_STATE_MEMORY_BUDGET")
+            .ok()
+            .and_then(|bytes| bytes.parse().ok())
+            .unwrap_or(DEFAULT_MEMORY_BUDGET);
+        Self::with_memory_budget(budget)
+    }
+
+    /// Keeps at most `bytes` of serialised entries in memory.
+    fn with_memory_budget(bytes: usize) -> Self {
+        SpillingBackend {
+            budget: Rc::new(RefCell::new(MemoryBudget::new(bytes))),
+            disk: D::new(),
+            maps: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn get_managed_count(&self, name: &str) -> Box<ManagedCount> {
+        Box::new(SpillingManagedCount::new(self.get_managed_map(name)))
+    }
+
+    fn get_managed_value<V: 'static + FasterValue + FasterRmw>(
+        &self,
+        name: &str,
+    ) -> Box<ManagedValue<V>> {
+        Box::new(SpillingManagedValue::new(self.get_managed_map(name)))
+    }
+
+    fn get_managed_map<K, V>(&self, name: &str) -> Box<ManagedMap<K, V>>
+    where
+        K: 'static + FasterKey + Hash + Eq,
+        V: 'static + FasterValue + FasterRmw,
+    {
+        let mut maps = self.maps.borrow_mut();
+        let shared = maps
+            .get(name)
+            .and_then(|tiers| tiers.upgrade())
+            .and_then(|tiers| tiers.downcast::<RefCell<Tiers<K, V>>>().ok());
+        let tiers = match shared {
+            Some(tiers) => tiers,
+            None => {
+                let tiers = Tiers::register(&self.budget, self.disk.get_managed_map(name));
+                let any: Rc<Any> = tiers.clone();
+                maps.insert(name.to_string(), Rc::downgrade(&any));
+                tiers
+            }
+        };
+        Box::new(SpillingManagedMap::new(Rc::clone(&self.budget), tiers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpillingBackend;
+    use crate::backends::InMemoryBackend;
+    use crate::{StateBackend, StateHandle};
+    use std::rc::Rc;
+
+    #[test]
+    fn backend_shares_entries_between_handles() {
+        let backend: SpillingBackend<InMemoryBackend> = SpillingBackend::with_memory_budget(1024);
+        let mut first = backend.get_managed_map::<u64, u64>("map");
+        let second = backend.get_managed_map::<u64, u64>("map");
+
+        first.insert(1, 10);
+        let used = backend.memory_used();
+        assert_eq!(second.get(&1), Some(Rc::new(10)));
+        assert_eq!(backend.memory_used(), used);
+
+        drop(first);
+        assert_eq!(backend.memory_used(), used);
+        drop(second);
+        assert_eq!(backend.memory_used(), 0);
+
+        let third = backend.get_managed_map::<u64, u64>("map");
+        assert_eq!(third.get(&1), Some(Rc::new(10)));
+    }
+
+    #[test]
+    fn backend_charges_and_spills_values_and_counts() {
+        let backend: SpillingBackend<InMemoryBackend> = SpillingBackend::with_memory_budget(64);
+        let mut value = backend.get_managed_value::<Vec<u64>>("value");
+        let mut count = backend.get_managed_count("count");
+
+        count.increase(5);
+        count.decrease(2);
+        assert_eq!(count.get(), 3);
+        assert!(backend.memory_used() > 0);
+
+        value.set((0..100).collect());
+        assert!(backend.memory_used() <= 64);
+        assert_eq!(value.get(), Some(Rc::new((0..100).collect())));
+        assert_eq!(count.get(), 3);
+
+        assert_eq!(value.take(), Some((0..100).collect()));
+        assert_eq!(value.get(), None);
+        count.set(7);
+        assert_eq!(count.get(), 7);
+    }
+
+    #[test]
+    fn handles_create_backends_with_their_budget() {
+        let handle = StateHandle::new(Rc::new(InMemoryBackend::new()), "0").with_memory_budget(64);
+        handle.set_operator_memory_budget("Large", 4096);
+        let small = handle.create_operator_handle("Small", &[0, 1]);
+        let large = handle.create_operator_handle("Large", &[0, 2]);
+        assert_eq!(small.memory_budget(), Some(64));
+        assert_eq!(large.memory_budget(), Some(4096));
+
+        let small = small.create_backend::<SpillingBackend<InMemoryBackend>>();
+        let large = large.create_backend::<SpillingBackend<InMemoryBackend>>();
+        let mut maps = vec![
+            small.get_managed_map::<u64, u64>("map"),
+            large.get_managed_map::<u64, u64>("map"),
+        ];
+        for map in maps.iter_mut() {
+            for key in 0..100 {
+                map.insert(key, key);
+            }
+        }
+        assert!(small.memory_used() <= 64);
+        assert!(large.memory_used() > 64);
+    }
+}
@@ -0,0 +1,229 @@
+//! Secondary indexes over managed maps.
+//!
+//! An `IndexedManagedMap` wraps a primary map and any number of named indexes, each defined by
+//! an extractor from an entry to its index key. Every index is itself stored in a managed map,
+//! from serialised index keys to the set of primary keys carrying that index key, and is kept
+//! consistent by `insert`, `remove` and `rmw`. Indexes only cover entries written after they
+//! were declared, so they should be added before the map is first written to.
+
+use crate::primitives::ManagedMap;
+use bincode::{deserialize, serialize};
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+use std::hash::Hash;
+use std::rc::Rc;
+
+/// Serialised primary keys sharing an index key.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySet {
+    pub keys: Vec<Vec<u8>>,
+}
+
+impl FasterRmw for KeySet {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut keys = self.keys.clone();
+        for key in modification.keys {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        KeySet { keys }
+    }
+}
+
+struct SecondaryIndex<K, V> {
+    name: String,
+    extractor: Box<Fn(&K, &V) -> Vec<u8>>,
+    entries: Box<ManagedMap<Vec<u8>, KeySet>>,
+}
+
+impl<K, V> SecondaryIndex<K, V> {
+    fn add(&mut self, key: &[u8], index_key: Vec<u8>) {
+        self.entries.rmw(
+            index_key,
+            KeySet {
+                keys: vec![key.to_vec()],
+            },
+        );
+    }
+
+    fn delete(&mut self, key: &[u8], index_key: &Vec<u8>) {
+        if let Some(mut key_set) = self.entries.remove(index_key) {
+            key_set.keys.retain(|k| &k[..] != key);
+            if !key_set.keys.is_empty() {
+                self.entries.insert(index_key.clone(), key_set);
+            }
+        }
+    }
+}
+
+pub struct IndexedManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    primary: Box<ManagedMap<K, V>>,
+    indexes: Vec<SecondaryIndex<K, V>>,
+    index_storage: Box<Fn(&str) -> Box<ManagedMap<Vec<u8>, KeySet>>>,
+}
+
+impl<K, V> IndexedManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    /// Wraps `primary`, obtaining the storage of each declared index from `index_storage`.
+    pub fn new(
+        primary: Box<ManagedMap<K, V>>,
+        index_storage: Box<Fn(&str) -> Box<ManagedMap<Vec<u8>, KeySet>>>,
+    ) -> Self {
+        IndexedManagedMap {
+            primary,
+            indexes: Vec::new(),
+            index_storage,
+        }
+    }
+
+    /// Declares an index named `name`, keyed by the result of `extractor`.
+    pub fn add_index<I, F>(&mut self, name: &str, extractor: F)
+    where
+        I: FasterKey,
+        F: Fn(&K, &V) -> I + 'static,
+    {
+        assert!(
+            self.indexes.iter().all(|index| index.name != name),
+            "Index {} declared twice",
+            name
+        );
+        self.indexes.push(SecondaryIndex {
+            name: name.to_owned(),
+            extractor: Box::new(move |key, value| serialize(&extractor(key, value)).unwrap()),
+            entries: (self.index_storage)(name),
+        });
+    }
+
+    /// The primary keys of entries whose key in index `name` is `index_key`.
+    pub fn lookup<I: FasterKey>(&self, name: &str, index_key: &I) -> Vec<K> {
+        let index = self
+            .indexes
+            .iter()
+            .find(|index| index.name == name)
+            .unwrap_or_else(|| panic!("No index named {}", name));
+        match index.entries.get(&serialize(index_key).unwrap()) {
+            None => Vec::new(),
+            Some(key_set) => key_set
+                .keys
+                .iter()
+                .map(|key| deserialize(key).unwrap())
+                .collect(),
+        }
+    }
+
+    /// The entries whose key in index `name` is `index_key`.
+    pub fn get_by_index<I: FasterKey>(&self, name: &str, index_key: &I) -> Vec<(K, Rc<V>)> {
+        self.lookup(name, index_key)
+            .into_iter()
+            .filter_map(|key| self.primary.get(&key).map(|value| (key, value)))
+            .collect()
+    }
+
+    fn unindex(&mut self, key: &[u8], old: &K, value: &V) {
+        for index in self.indexes.iter_mut() {
+            let index_key = (index.extractor)(old, value);
+            index.delete(key, &index_key);
+        }
+    }
+
+    fn index(&mut self, key: &[u8], new: &K, value: &V) {
+        for index in self.indexes.iter_mut() {
+            let index_key = (index.extractor)(new, value);
+            index.add(key, index_key);
+        }
+    }
+}
+
+impl<K, V> ManagedMap<K, V> for IndexedManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    fn insert(&mut self, key: K, value: V) {
+        let serialised_key = serialize(&key).unwrap();
+        if let Some(old) = self.primary.get(&key) {
+            self.unindex(&serialised_key, &key, &old);
+        }
+        self.index(&serialised_key, &key, &value);
+        self.primary.insert(key, value);
+    }
+
+    fn get(&self, key: &K) -> Option<Rc<V>> {
+        self.primary.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let old = self.primary.remove(key)?;
+        let serialised_key = serialize(key).unwrap();
+        self.unindex(&serialised_key, key, &old);
+        Some(old)
+    }
+
+    fn rmw(&mut self, key: K, modification: V) {
+        let new_value = match self.primary.get(&key) {
+            None => modification,
+            Some(value) => value.rmw(modification),
+        };
+        self.insert(key, new_value);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.primary.contains(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backends::InMemoryBackend;
+    use crate::primitives::ManagedMap;
+    use crate::{StateBackend, StateHandle};
+    use std::rc::Rc;
+
+    // Users by id, with the session as value.
+    fn users() -> super::IndexedManagedMap<u64, u64> {
+        let handle = StateHandle::new(Rc::new(InMemoryBackend::new()), "0");
+        let mut users = handle.get_indexed_managed_map("users");
+        users.add_index("session", |_id, session: &u64| *session);
+        users.add_index("parity", |id: &u64, _session| id % 2 == 0);
+        users
+    }
+
+    #[test]
+    fn insert_updates_indexes() {
+        let mut users = users();
+        users.insert(1, 10);
+        users.insert(2, 10);
+        users.insert(3, 20);
+
+        let mut in_session = users.lookup("session", &10u64);
+        in_session.sort();
+        assert_eq!(in_session, vec![1, 2]);
+        assert_eq!(users.lookup("parity", &true), vec![2]);
+
+        users.insert(2, 20);
+        assert_eq!(users.lookup("session", &10u64), vec![1]);
+        let mut in_session = users.lookup("session", &20u64);
+        in_session.sort();
+        assert_eq!(in_session, vec![2, 3]);
+    }
+
+    #[test]
+    fn remove_and_rmw_update_indexes() {
+        let mut users = users();
+        users.insert(1, 10);
+        users.rmw(1, 5);
+        assert!(users.lookup("session", &10u64).is_empty());
+        assert_eq!(users.get_by_index("session", &15u64), vec![(1, Rc::new(15))]);
+
+        assert_eq!(users.remove(&1), Some(15));
+        assert!(users.lookup("session", &15u64).is_empty());
+        assert!(users.lookup("parity", &false).is_empty());
+    }
+}
@@ -1,16 +1,37 @@
 extern crate faster_rs;
+#[macro_use]
+extern crate serde_derive;
 
+use crate::indexing::IndexedManagedMap;
+use crate::namespace::Namespace;
 use crate::primitives::{ManagedCount, ManagedMap, ManagedValue};
+use crate::registry::{PrimitiveKind, RegisteredPrimitive, StateRegistry};
+use crate::versioning::{Migrations, VersionedManagedMap, VersionedManagedValue};
 use faster_rs::{FasterKey, FasterRmw, FasterValue};
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::rc::Rc;
 
 pub mod backends;
+pub mod indexing;
+pub mod namespace;
 pub mod primitives;
+pub mod registry;
+pub mod versioning;
 
 pub trait StateBackend: 'static {
     fn new() -> Self;
 
+    /// Creates a backend that holds at most `bytes` of state in memory. Backends that do not
+    /// bound their memory use ignore the budget.
+    fn with_memory_budget(_bytes: usize) -> Self
+    where
+        Self: Sized,
+    {
+        Self::new()
+    }
+
     fn get_managed_count(&self, name: &str) -> Box<ManagedCount>;
     fn get_managed_value<V: 'static + FasterValue + FasterRmw>(
         &self,
@@ -24,34 +45,129 @@ pub trait StateBackend: 'static {
 
 pub struct StateHandle<S: StateBackend> {
     backend: Rc<S>,
-    name: String,
+    root: Namespace,
+    namespace: Namespace,
+    operator: Option<String>,
+    registry: Rc<RefCell<StateRegistry>>,
+    memory_budget: Option<usize>,
+    // Budgets of operators set through any handle derived from the same root, by operator name.
+    operator_budgets: Rc<RefCell<HashMap<String, usize>>>,
 }
 
 impl<S: StateBackend> StateHandle<S> {
     pub fn new(backend: Rc<S>, name: &str) -> Self {
+        let root = Namespace::root(name);
         StateHandle {
             backend,
-            name: name.to_owned(),
+            namespace: root.clone(),
+            root,
+            operator: None,
+            registry: Rc::new(RefCell::new(StateRegistry::new())),
+            memory_budget: None,
+            operator_budgets: Rc::new(RefCell::new(HashMap::new())),
         }
     }
 
     pub fn create_sub_handle(&self, name: &str) -> Self {
         StateHandle {
             backend: Rc::clone(&self.backend),
-            name: [&self.name, name].join("."),
+            root: self.root.clone(),
+            namespace: self.namespace.child(name),
+            operator: self.operator.clone(),
+            registry: Rc::clone(&self.registry),
+            memory_budget: self.memory_budget,
+            operator_budgets: Rc::clone(&self.operator_budgets),
+        }
+    }
+
+    /// Creates the handle of an operator, namespaced by the operator's full address below
+    /// the root of this handle rather than by the namespace of the current scope.
+    ///
+    /// The handle carries the memory budget set for `operator` through
+    /// `set_operator_memory_budget`, or else the budget of this handle.
+    pub fn create_operator_handle(&self, operator: &str, address: &[usize]) -> Self {
+        let memory_budget = self.operator_budgets.borrow().get(operator).cloned();
+        StateHandle {
+            backend: Rc::clone(&self.backend),
+            root: self.root.clone(),
+            namespace: self.root.with_address(address),
+            operator: Some(operator.to_owned()),
+            registry: Rc::clone(&self.registry),
+            memory_budget: memory_budget.or(self.memory_budget),
+            operator_budgets: Rc::clone(&self.operator_budgets),
+        }
+    }
+
+    /// A handle whose new backends hold at most `bytes` of state in memory.
+    pub fn with_memory_budget(&self, bytes: usize) -> Self {
+        let mut handle = self.clone();
+        handle.memory_budget = Some(bytes);
+        handle
+    }
+
+    /// Sets the memory budget of the backends of operators named `operator` created after this
+    /// call, in the dataflow of this handle.
+    pub fn set_operator_memory_budget(&self, operator: &str, bytes: usize) {
+        self.operator_budgets
+            .borrow_mut()
+            .insert(operator.to_owned(), bytes);
+    }
+
+    /// The memory budget new backends of this handle are created with, if one is set.
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// Creates a backend with the memory budget of this handle, if one is set.
+    pub fn create_backend<S2: StateBackend>(&self) -> Rc<S2> {
+        match self.memory_budget {
+            Some(bytes) => Rc::new(S2::with_memory_budget(bytes)),
+            None => Rc::new(S2::new()),
         }
     }
 
     pub fn spawn_new_backend(&self) -> Self {
+        self.with_backend(self.create_backend())
+    }
+
+    /// Moves the namespace and registry of this handle onto a different backend.
+    pub fn with_backend<S2: StateBackend>(&self, backend: Rc<S2>) -> StateHandle<S2> {
         StateHandle {
-            backend: Rc::new(S::new()),
-            name: self.name.clone()
+            backend,
+            root: self.root.clone(),
+            namespace: self.namespace.clone(),
+            operator: self.operator.clone(),
+            registry: Rc::clone(&self.registry),
+            memory_budget: self.memory_budget,
+            operator_budgets: Rc::clone(&self.operator_budgets),
         }
     }
 
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    pub fn operator(&self) -> Option<&str> {
+        self.operator.as_ref().map(|name| &name[..])
+    }
+
+    /// The primitives created through this handle and all handles derived from the same root.
+    pub fn registry(&self) -> Ref<StateRegistry> {
+        self.registry.borrow()
+    }
+
+    fn physical_name(&self, name: &str, kind: PrimitiveKind) -> String {
+        let physical_name = self.namespace.child(name).as_str().to_owned();
+        self.registry.borrow_mut().register(RegisteredPrimitive {
+            name: physical_name.clone(),
+            kind,
+            operator: self.operator.clone(),
+        });
+        physical_name
+    }
+
     pub fn get_managed_count(&self, name: &str) -> Box<ManagedCount> {
-        let mut physical_name = self.name.clone();
-        physical_name.push_str(name);
+        let physical_name = self.physical_name(name, PrimitiveKind::Count);
         self.backend.get_managed_count(&physical_name)
     }
 
@@ -60,8 +176,7 @@ impl<S: StateBackend> StateHandle<S> {
         K: 'static + FasterKey + Hash + Eq,
         V: 'static + FasterValue + FasterRmw,
     {
-        let mut physical_name = self.name.clone();
-        physical_name.push_str(name);
+        let physical_name = self.physical_name(name, PrimitiveKind::Map);
         self.backend.get_managed_map(&physical_name)
     }
 
@@ -69,17 +184,56 @@ impl<S: StateBackend> StateHandle<S> {
         &self,
         name: &str,
     ) -> Box<ManagedValue<V>> {
-        let mut physical_name = self.name.clone();
-        physical_name.push_str(name);
+        let physical_name = self.physical_name(name, PrimitiveKind::Value);
         self.backend.get_managed_value(&physical_name)
     }
+
+    /// A map supporting secondary indexes, which are stored in maps below the map's own name.
+    pub fn get_indexed_managed_map<K, V>(&self, name: &str) -> IndexedManagedMap<K, V>
+    where
+        K: 'static + FasterKey + Hash + Eq,
+        V: 'static + FasterValue + FasterRmw,
+    {
+        let index_handle = self.create_sub_handle(name);
+        IndexedManagedMap::new(
+            self.get_managed_map(name),
+            Box::new(move |index| index_handle.get_managed_map(index)),
+        )
+    }
+
+    /// A map whose values carry their schema version, and are migrated to the current one on read.
+    pub fn get_versioned_managed_map<K, V>(
+        &self,
+        name: &str,
+        migrations: Rc<Migrations<V>>,
+    ) -> VersionedManagedMap<K, V>
+    where
+        K: 'static + FasterKey + Hash + Eq,
+        V: 'static + FasterValue + FasterRmw,
+    {
+        VersionedManagedMap::new(self.get_managed_map(name), migrations)
+    }
+
+    /// A value carrying its schema version, and migrated to the current one on read.
+    pub fn get_versioned_managed_value<V: 'static + FasterValue + FasterRmw>(
+        &self,
+        name: &str,
+        migrations: Rc<Migrations<V>>,
+    ) -> VersionedManagedValue<V> {
+        VersionedManagedValue::new(self.get_managed_value(name), migrations)
+    }
 }
 
 impl<S: StateBackend> Clone for StateHandle<S> {
     fn clone(&self) -> Self {
         StateHandle {
             backend: Rc::clone(&self.backend),
-            name: self.name.clone(),
+            root: self.root.clone(),
+            namespace: self.namespace.clone(),
+            operator: self.operator.clone(),
+            registry: Rc::clone(&self.registry),
+            memory_budget: self.memory_budget,
+            operator_budgets: Rc::clone(&self.operator_budgets),
         }
     }
 }
@@ -0,0 +1,81 @@
+//! Unambiguous physical names for managed state.
+//!
+//! A namespace is a sequence of components, typically the worker index followed by the
+//! address of the operator that owns the state. Each component is written with a length
+//! prefix (`<len>:<component>`), so two different sequences of components can never
+//! produce the same physical name, even when their plain concatenations coincide.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Namespace {
+    encoded: String,
+}
+
+impl Namespace {
+    /// A namespace with a single component.
+    pub fn root(component: &str) -> Self {
+        Namespace {
+            encoded: String::new(),
+        }
+        .child(component)
+    }
+
+    /// Extends the namespace by one component.
+    pub fn child(&self, component: &str) -> Self {
+        let mut encoded = self.encoded.clone();
+        encoded.push_str(&component.len().to_string());
+        encoded.push(':');
+        encoded.push_str(component);
+        Namespace { encoded }
+    }
+
+    /// Extends the namespace by the coordinates of an operator address.
+    pub fn with_address(&self, address: &[usize]) -> Self {
+        address
+            .iter()
+            .fold(self.clone(), |namespace, index| namespace.child(&index.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.encoded
+    }
+
+    /// Recovers the components the namespace was built from.
+    pub fn components(&self) -> Vec<String> {
+        let mut components = Vec::new();
+        let mut rest = &self.encoded[..];
+        while let Some(colon) = rest.find(':') {
+            let length: usize = rest[..colon].parse().expect("Malformed namespace");
+            let start = colon + 1;
+            components.push(rest[start..start + length].to_owned());
+            rest = &rest[start + length..];
+        }
+        components
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.components().join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Namespace;
+
+    #[test]
+    fn concatenations_do_not_collide() {
+        let first = Namespace::root("1").child("1counts");
+        let second = Namespace::root("11").child("counts");
+        assert_ne!(first.as_str(), second.as_str());
+    }
+
+    #[test]
+    fn components_round_trip() {
+        let namespace = Namespace::root("3").with_address(&[0, 12]).child("a:b");
+        assert_eq!(namespace.components(), vec!["3", "0", "12", "a:b"]);
+        assert_eq!(namespace.to_string(), "3/0/12/a:b");
+    }
+}
@@ -0,0 +1,74 @@
+//! Bookkeeping of the managed primitives created through a family of `StateHandle`s.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrimitiveKind {
+    Count,
+    Value,
+    Map,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisteredPrimitive {
+    /// The physical name the primitive was requested under from the backend.
+    pub name: String,
+    pub kind: PrimitiveKind,
+    /// The name of the operator owning the primitive, if it was created by an operator.
+    pub operator: Option<String>,
+}
+
+#[derive(Default)]
+pub struct StateRegistry {
+    primitives: Vec<RegisteredPrimitive>,
+}
+
+impl StateRegistry {
+    pub fn new() -> Self {
+        StateRegistry {
+            primitives: Vec::new(),
+        }
+    }
+
+    /// Records a primitive, ignoring repeated requests for the same physical name.
+    pub fn register(&mut self, primitive: RegisteredPrimitive) {
+        if !self.primitives.iter().any(|p| p.name == primitive.name) {
+            self.primitives.push(primitive);
+        }
+    }
+
+    pub fn primitives(&self) -> &[RegisteredPrimitive] {
+        &self.primitives
+    }
+
+    pub fn primitives_of<'a>(
+        &'a self,
+        operator: &'a str,
+    ) -> impl Iterator<Item = &'a RegisteredPrimitive> + 'a {
+        self.primitives
+            .iter()
+            .filter(move |p| p.operator.as_ref().map(|o| o == operator).unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backends::InMemoryNativeBackend;
+    use crate::registry::PrimitiveKind;
+    use crate::{StateBackend, StateHandle};
+    use std::rc::Rc;
+
+    #[test]
+    fn registry_lists_primitives_by_operator() {
+        let handle = StateHandle::new(Rc::new(InMemoryNativeBackend::new()), "0");
+        let operator = handle.create_operator_handle("Count", &[0, 3]);
+        let _count = operator.get_managed_count("count");
+        let _again = operator.get_managed_count("count");
+        let _map = handle.get_managed_map::<u64, u64>("map");
+
+        let registry = handle.registry();
+        assert_eq!(registry.primitives().len(), 2);
+        let owned = registry.primitives_of("Count").collect::<Vec<_>>();
+        assert_eq!(owned.len(), 1);
+        assert_eq!(owned[0].kind, PrimitiveKind::Count);
+        assert_eq!(owned[0].name, operator.namespace().child("count").as_str());
+    }
+}
@@ -0,0 +1,300 @@
+//! Versioned envelopes for managed state, and migrations between value schemas.
+//!
+//! Values written through a versioned primitive are stored as an `Envelope`, carrying the schema
+//! version they were encoded with next to their serialised bytes. When an envelope with an older
+//! version is read, the registered migrations are applied one version at a time until the current
+//! schema is reached. Reads migrate lazily and write the upgraded envelope back; the `migrate`
+//! methods upgrade eagerly, for example while restoring state whose keys are known.
+
+use crate::primitives::{ManagedMap, ManagedValue};
+use bincode::{deserialize, serialize};
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u32,
+    pub bytes: Vec<u8>,
+}
+
+// Versioned primitives decode envelopes to apply `rmw`, so envelopes only ever get replaced.
+impl FasterRmw for Envelope {
+    fn rmw(&self, modification: Self) -> Self {
+        modification
+    }
+}
+
+pub struct Migrations<V: FasterValue> {
+    current: u32,
+    steps: HashMap<u32, Box<Fn(&[u8]) -> Vec<u8>>>,
+    phantom: PhantomData<V>,
+}
+
+impl<V: FasterValue> Migrations<V> {
+    /// Migrations for values whose current schema has version `current`.
+    pub fn new(current: u32) -> Self {
+        Migrations {
+            current,
+            steps: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Registers the upgrade of a value from schema version `from` to version `from + 1`.
+    pub fn register<Old, New, F>(&mut self, from: u32, upgrade: F) -> &mut Self
+    where
+        Old: FasterValue,
+        New: FasterValue,
+        F: Fn(Old) -> New + 'static,
+    {
+        assert!(from < self.current, "Migrations must start below the current version");
+        self.steps.insert(
+            from,
+            Box::new(move |bytes| {
+                let old: Old = deserialize(bytes).expect("Unable to decode value for migration");
+                serialize(&upgrade(old)).unwrap()
+            }),
+        );
+        self
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.current
+    }
+
+    pub fn encode(&self, value: &V) -> Envelope {
+        Envelope {
+            version: self.current,
+            bytes: serialize(value).unwrap(),
+        }
+    }
+
+    /// Brings an envelope up to the current version, returning `None` if it already was.
+    pub fn upgrade(&self, envelope: &Envelope) -> Option<Envelope> {
+        if envelope.version == self.current {
+            return None;
+        }
+        assert!(
+            envelope.version < self.current,
+            "Envelope version {} is newer than the current schema {}",
+            envelope.version,
+            self.current
+        );
+        let mut bytes = envelope.bytes.clone();
+        for version in envelope.version..self.current {
+            let step = self.steps.get(&version).unwrap_or_else(|| {
+                panic!("No migration registered from schema version {}", version)
+            });
+            bytes = step(&bytes);
+        }
+        Some(Envelope {
+            version: self.current,
+            bytes,
+        })
+    }
+
+    pub fn decode(&self, envelope: &Envelope) -> V {
+        assert_eq!(envelope.version, self.current);
+        deserialize(&envelope.bytes).expect("Unable to decode versioned value")
+    }
+}
+
+pub struct VersionedManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    inner: RefCell<Box<ManagedMap<K, Envelope>>>,
+    migrations: Rc<Migrations<V>>,
+}
+
+impl<K, V> VersionedManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    pub fn new(inner: Box<ManagedMap<K, Envelope>>, migrations: Rc<Migrations<V>>) -> Self {
+        VersionedManagedMap {
+            inner: RefCell::new(inner),
+            migrations,
+        }
+    }
+
+    fn read(&self, key: &K) -> Option<Envelope> {
+        let envelope = self.inner.borrow().get(key)?;
+        match self.migrations.upgrade(&envelope) {
+            None => Some((*envelope).clone()),
+            Some(upgraded) => {
+                // Keys are not `Clone`, so the write-back uses a deserialised copy of the key.
+                let owned_key = deserialize(&serialize(key).unwrap()).unwrap();
+                self.inner.borrow_mut().insert(owned_key, upgraded.clone());
+                Some(upgraded)
+            }
+        }
+    }
+
+    /// Eagerly upgrades the values stored under `keys` to the current schema.
+    pub fn migrate<I: IntoIterator<Item = K>>(&mut self, keys: I) {
+        for key in keys {
+            self.read(&key);
+        }
+    }
+}
+
+impl<K, V> ManagedMap<K, V> for VersionedManagedMap<K, V>
+where
+    K: 'static + FasterKey + Hash + Eq,
+    V: 'static + FasterValue + FasterRmw,
+{
+    fn insert(&mut self, key: K, value: V) {
+        let envelope = self.migrations.encode(&value);
+        self.inner.borrow_mut().insert(key, envelope);
+    }
+
+    fn get(&self, key: &K) -> Option<Rc<V>> {
+        self.read(key)
+            .map(|envelope| Rc::new(self.migrations.decode(&envelope)))
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let envelope = self.inner.borrow_mut().remove(key)?;
+        let envelope = self.migrations.upgrade(&envelope).unwrap_or(envelope);
+        Some(self.migrations.decode(&envelope))
+    }
+
+    fn rmw(&mut self, key: K, modification: V) {
+        let new_value = match self.get(&key) {
+            None => modification,
+            Some(value) => value.rmw(modification),
+        };
+        self.insert(key, new_value);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.inner.borrow().contains(key)
+    }
+}
+
+pub struct VersionedManagedValue<V: 'static + FasterValue + FasterRmw> {
+    inner: RefCell<Box<ManagedValue<Envelope>>>,
+    migrations: Rc<Migrations<V>>,
+}
+
+impl<V: 'static + FasterValue + FasterRmw> VersionedManagedValue<V> {
+    pub fn new(inner: Box<ManagedValue<Envelope>>, migrations: Rc<Migrations<V>>) -> Self {
+        VersionedManagedValue {
+            inner: RefCell::new(inner),
+            migrations,
+        }
+    }
+
+    fn read(&self) -> Option<Envelope> {
+        let envelope = self.inner.borrow().get()?;
+        match self.migrations.upgrade(&envelope) {
+            None => Some((*envelope).clone()),
+            Some(upgraded) => {
+                self.inner.borrow_mut().set(upgraded.clone());
+                Some(upgraded)
+            }
+        }
+    }
+
+    /// Eagerly upgrades the stored value to the current schema.
+    pub fn migrate(&mut self) {
+        self.read();
+    }
+}
+
+impl<V: 'static + FasterValue + FasterRmw> ManagedValue<V> for VersionedManagedValue<V> {
+    fn set(&mut self, value: V) {
+        let envelope = self.migrations.encode(&value);
+        self.inner.borrow_mut().set(envelope);
+    }
+
+    fn get(&self) -> Option<Rc<V>> {
+        self.read()
+            .map(|envelope| Rc::new(self.migrations.decode(&envelope)))
+    }
+
+    fn take(&mut self) -> Option<V> {
+        let envelope = self.inner.borrow_mut().take()?;
+        let envelope = self.migrations.upgrade(&envelope).unwrap_or(envelope);
+        Some(self.migrations.decode(&envelope))
+    }
+
+    fn rmw(&mut self, modification: V) {
+        let new_value = match self.get() {
+            None => modification,
+            Some(value) => value.rmw(modification),
+        };
+        self.set(new_value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Migrations, VersionedManagedMap, VersionedManagedValue};
+    use crate::backends::InMemoryBackend;
+    use crate::primitives::{ManagedMap, ManagedValue};
+    use crate::StateBackend;
+    use std::rc::Rc;
+
+    fn migrations() -> Rc<Migrations<String>> {
+        let mut migrations = Migrations::new(2);
+        migrations
+            .register(0, |count: u32| count as u64 * 2)
+            .register(1, |doubled: u64| format!("{}", doubled));
+        Rc::new(migrations)
+    }
+
+    #[test]
+    fn map_reads_migrate_old_versions() {
+        let backend = InMemoryBackend::new();
+        {
+            let mut old: VersionedManagedMap<u64, u32> = VersionedManagedMap::new(
+                backend.get_managed_map("map"),
+                Rc::new(Migrations::new(0)),
+            );
+            old.insert(1, 21);
+        }
+        let map: VersionedManagedMap<u64, String> =
+            VersionedManagedMap::new(backend.get_managed_map("map"), migrations());
+        assert_eq!(map.get(&1), Some(Rc::new("42".to_string())));
+        assert_eq!(map.get(&1), Some(Rc::new("42".to_string())));
+    }
+
+    #[test]
+    fn value_eager_migration() {
+        let backend = InMemoryBackend::new();
+        {
+            let mut old: VersionedManagedValue<u64> = VersionedManagedValue::new(
+                backend.get_managed_value("value"),
+                Rc::new(Migrations::new(1)),
+            );
+            old.set(7);
+        }
+        let mut value = VersionedManagedValue::new(backend.get_managed_value("value"), migrations());
+        value.migrate();
+        assert_eq!(value.take(), Some("7".to_string()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn missing_migration_panics() {
+        let backend = InMemoryBackend::new();
+        {
+            let mut old: VersionedManagedValue<u64> = VersionedManagedValue::new(
+                backend.get_managed_value("value"),
+                Rc::new(Migrations::new(0)),
+            );
+            old.set(7);
+        }
+        let value: VersionedManagedValue<u64> =
+            VersionedManagedValue::new(backend.get_managed_value("value"), Rc::new(Migrations::new(3)));
+        value.get();
+    }
+}
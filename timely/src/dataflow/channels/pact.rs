@@ -7,6 +7,8 @@
 //! The only requirement of a pact is that it not alter the number of `D` records at each time `T`.
 //! The progress tracking logic assumes that this number is independent of the pact used.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::marker::PhantomData;
 
 use crate::communication::{Push, Pull, Data};
@@ -67,27 +69,284 @@ impl<T: Eq+Data+Clone, D: Data+Clone, F: Fn(&D)->u64+'static> ParallelizationCon
     }
 }
 
-// /// An exchange between multiple observers by time and data
-// pub struct TimeExchange<D, T, F: Fn(&T, &D)->u64+'static> { hash_func: F, phantom: PhantomData<(T, D)>, }
-// impl<D, T, F: Fn(&T, &D)->u64> TimeExchange<D, T, F> {
-//     /// Allocates a new `TimeExchange` pact from a distribution function.
-//     pub fn new(func: F) -> TimeExchange<D, T, F> {
-//         TimeExchange {
-//             hash_func:  func,
-//             phantom:    PhantomData,
-//         }
-//     }
-// }
-
-// impl<T: Eq+Data+Clone, D: Data+Clone, F: Fn(&T, &D)->u64+'static> ParallelizationContract<T, D> for TimeExchange<D, T, F> {
-//     type Pusher = ExchangePusher<T, D, Pusher<T, D, Box<Push<CommMessage<Message<T, D>>>>>, F>;
-//     type Puller = Puller<T, D, Box<Pull<CommMessage<Message<T, D>>>>>;
-//     fn connect<A: AsWorker>(self, allocator: &mut A, identifier: usize, logging: Logger) -> (Self::Pusher, Self::Puller) {
-//         let (senders, receiver, channel_id) = allocator.allocate::<Message<T, D>>();
-//         let senders = senders.into_iter().enumerate().map(|(i,x)| Pusher::new(x, allocator.index(), i, identifier, channel_id, logging.clone())).collect::<Vec<_>>();
-//         (ExchangePusher::new(senders, self.hash_func), Puller::new(receiver, allocator.index(), identifier, channel_id, logging.clone()))
-//     }
-// }
+/// An exchange between multiple observers by time and data
+pub struct TimeExchange<D, T, F: Fn(&T, &D)->u64+'static> { hash_func: F, phantom: PhantomData<(T, D)>, }
+impl<D, T, F: Fn(&T, &D)->u64> TimeExchange<D, T, F> {
+    /// Allocates a new `TimeExchange` pact from a distribution function.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Inspect};
+    /// use timely::dataflow::operators::generic::operator::Operator;
+    /// use timely::dataflow::channels::pact::TimeExchange;
+    ///
+    /// timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .unary(TimeExchange::new(|time: &u64, x: &u64| time + x), "ByEpoch", |_cap, _info, _state_handle| {
+    ///             let mut vector = Vec::new();
+    ///             move |input, output| {
+    ///                 input.for_each(|time, data| {
+    ///                     data.swap(&mut vector);
+    ///                     output.session(&time).give_vec(&mut vector);
+    ///                 });
+    ///             }
+    ///         })
+    ///         .inspect(|x| assert!(*x < 10));
+    /// });
+    /// ```
+    pub fn new(func: F) -> TimeExchange<D, T, F> {
+        TimeExchange {
+            hash_func:  func,
+            phantom:    PhantomData,
+        }
+    }
+}
+
+impl<T: Eq+Data+Clone, D: Data+Clone, F: Fn(&T, &D)->u64+'static> ParallelizationContract<T, D> for TimeExchange<D, T, F> {
+    type Pusher = Box<Push<Bundle<T, D>>>;
+    type Puller = Box<Pull<Bundle<T, D>>>;
+    fn connect<A: AsWorker>(self, allocator: &mut A, identifier: usize, address: &[usize], logging: Option<Logger>) -> (Self::Pusher, Self::Puller) {
+        let (senders, receiver) = allocator.allocate::<Message<T, D>>(identifier, address);
+        let senders = senders.into_iter().enumerate().map(|(i,x)| LogPusher::new(x, allocator.index(), i, identifier, logging.clone())).collect::<Vec<_>>();
+        (Box::new(ExchangePusher::new(senders, self.hash_func)), Box::new(LogPuller::new(receiver, allocator.index(), identifier, logging.clone())))
+    }
+}
+
+/// An exchange sending each record to the worker whose index a routing function returns
+pub struct DirectExchange<D, F: Fn(&D)->usize+'static> { route_func: F, phantom: PhantomData<D>, }
+impl<D, F: Fn(&D)->usize> DirectExchange<D, F> {
+    /// Allocates a new `DirectExchange` pact from a routing function.
+    ///
+    /// The routing function must return the index of an existing worker, or the pact will panic.
+    pub fn new(func: F) -> DirectExchange<D, F> {
+        DirectExchange {
+            route_func: func,
+            phantom:    PhantomData,
+        }
+    }
+}
+
+impl<T: Eq+Data+Clone, D: Data+Clone, F: Fn(&D)->usize+'static> ParallelizationContract<T, D> for DirectExchange<D, F> {
+    type Pusher = Box<Push<Bundle<T, D>>>;
+    type Puller = Box<Pull<Bundle<T, D>>>;
+    fn connect<A: AsWorker>(self, allocator: &mut A, identifier: usize, address: &[usize], logging: Option<Logger>) -> (Self::Pusher, Self::Puller) {
+        let peers = allocator.peers();
+        let route_func = self.route_func;
+        let (senders, receiver) = allocator.allocate::<Message<T, D>>(identifier, address);
+        let senders = senders.into_iter().enumerate().map(|(i,x)| LogPusher::new(x, allocator.index(), i, identifier, logging.clone())).collect::<Vec<_>>();
+        // indices below `peers` are preserved by the masking or modulus of the exchange pusher.
+        let route = move |_: &T, d: &D| {
+            let index = route_func(d);
+            assert!(index < peers, "DirectExchange routed a record to worker {} of {}", index, peers);
+            index as u64
+        };
+        (Box::new(ExchangePusher::new(senders, route)), Box::new(LogPuller::new(receiver, allocator.index(), identifier, logging.clone())))
+    }
+}
+
+/// An exchange partitioning records by ranges of a key, delimited by split points
+pub struct RangeExchange<D, K: Ord, F: Fn(&D)->K+'static> { splits: Vec<K>, key_func: F, phantom: PhantomData<D>, }
+impl<D, K: Ord, F: Fn(&D)->K> RangeExchange<D, K, F> {
+    /// Allocates a new `RangeExchange` pact from sorted split points and a key function.
+    ///
+    /// The `n` split points delimit `n + 1` ranges of keys, with keys equal to a split point belonging
+    /// to the range it starts. Ranges are assigned to workers in order, in contiguous groups of equal size.
+    pub fn new(splits: Vec<K>, func: F) -> RangeExchange<D, K, F> {
+        assert!(splits.windows(2).all(|pair| pair[0] <= pair[1]), "RangeExchange split points must be sorted");
+        RangeExchange {
+            splits,
+            key_func:   func,
+            phantom:    PhantomData,
+        }
+    }
+
+    /// Allocates a new `RangeExchange` pact dividing keys into `ranges` ranges, using split points at
+    /// the quantiles of a sample of keys.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Inspect};
+    /// use timely::dataflow::operators::generic::operator::Operator;
+    /// use timely::dataflow::channels::pact::RangeExchange;
+    ///
+    /// timely::example(|scope| {
+    ///     let sample = vec![1u64, 5, 9, 13, 17, 21];
+    ///     (0u64..10).to_stream(scope)
+    ///         .unary(RangeExchange::from_sample(sample, 3, |x: &u64| *x), "ByRange", |_cap, _info, _state_handle| {
+    ///             let mut vector = Vec::new();
+    ///             move |input, output| {
+    ///                 input.for_each(|time, data| {
+    ///                     data.swap(&mut vector);
+    ///                     output.session(&time).give_vec(&mut vector);
+    ///                 });
+    ///             }
+    ///         })
+    ///         .inspect(|x| assert!(*x < 10));
+    /// });
+    /// ```
+    pub fn from_sample(mut sample: Vec<K>, ranges: usize, func: F) -> RangeExchange<D, K, F> {
+        assert!(ranges > 0, "RangeExchange requires at least one range");
+        sample.sort();
+        let positions = (1 .. ranges).map(|range| range * sample.len() / ranges).collect::<Vec<_>>();
+        let splits = sample
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| positions.contains(index))
+            .map(|(_, split)| split)
+            .collect();
+        Self::new(splits, func)
+    }
+}
+
+impl<T: Eq+Data+Clone, D: Data+Clone, K: Ord+'static, F: Fn(&D)->K+'static> ParallelizationContract<T, D> for RangeExchange<D, K, F> {
+    type Pusher = Box<Push<Bundle<T, D>>>;
+    type Puller = Box<Pull<Bundle<T, D>>>;
+    fn connect<A: AsWorker>(self, allocator: &mut A, identifier: usize, address: &[usize], logging: Option<Logger>) -> (Self::Pusher, Self::Puller) {
+        let peers = allocator.peers();
+        let splits = self.splits;
+        let key_func = self.key_func;
+        let (senders, receiver) = allocator.allocate::<Message<T, D>>(identifier, address);
+        let senders = senders.into_iter().enumerate().map(|(i,x)| LogPusher::new(x, allocator.index(), i, identifier, logging.clone())).collect::<Vec<_>>();
+        let ranges = splits.len() + 1;
+        let route = move |_: &T, d: &D| {
+            let key = key_func(d);
+            let range = match splits.binary_search(&key) {
+                Ok(mut index) => {
+                    // equal split points start empty ranges, so move past all of them.
+                    while index < splits.len() && splits[index] == key { index += 1; }
+                    index
+                },
+                Err(index) => index,
+            };
+            (range * peers / ranges) as u64
+        };
+        (Box::new(ExchangePusher::new(senders, route)), Box::new(LogPuller::new(receiver, allocator.index(), identifier, logging.clone())))
+    }
+}
+
+
+/// An exchange by key hash which spreads keys it observes to be heavy hitters over several workers
+///
+/// Each sending worker estimates the frequencies of key hashes from the records it sends, and routes
+/// the records of keys whose share of its records exceeds a threshold to one of several consecutive
+/// workers in turn. Records of a key may then reach more than one worker, and keyed computation must
+/// merge their results, as `SkewedAggregate` does.
+pub struct SkewExchange<D, F: Fn(&D)->u64+'static> {
+    hash_func: F,
+    threshold: Option<f64>,
+    split: Option<usize>,
+    capacity: usize,
+    phantom: PhantomData<D>,
+}
+impl<D, F: Fn(&D)->u64> SkewExchange<D, F> {
+    /// Allocates a new `SkewExchange` pact from a key hashing function.
+    ///
+    /// By default a key is hot once it accounts for more than one in `peers` of the records sent,
+    /// and hot keys are spread over all workers.
+    pub fn new(func: F) -> SkewExchange<D, F> {
+        SkewExchange {
+            hash_func:  func,
+            threshold:  None,
+            split:      None,
+            capacity:   64,
+            phantom:    PhantomData,
+        }
+    }
+    /// Sets the share of records above which a key is considered hot.
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        assert!(threshold > 0.0 && threshold <= 1.0, "SkewExchange threshold must be in (0, 1]");
+        self.threshold = Some(threshold);
+        self
+    }
+    /// Sets the number of workers each hot key is spread over.
+    pub fn split(mut self, split: usize) -> Self {
+        assert!(split > 0, "SkewExchange must split hot keys over at least one worker");
+        self.split = Some(split);
+        self
+    }
+    /// Sets the number of key hashes whose frequencies are tracked.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "SkewExchange must track at least one key");
+        self.capacity = capacity;
+        self
+    }
+}
+
+impl<T: Eq+Data+Clone, D: Data+Clone, F: Fn(&D)->u64+'static> ParallelizationContract<T, D> for SkewExchange<D, F> {
+    type Pusher = Box<Push<Bundle<T, D>>>;
+    type Puller = Box<Pull<Bundle<T, D>>>;
+    fn connect<A: AsWorker>(self, allocator: &mut A, identifier: usize, address: &[usize], logging: Option<Logger>) -> (Self::Pusher, Self::Puller) {
+        let peers = allocator.peers();
+        let split = self.split.unwrap_or(peers) as u64;
+        let hash_func = self.hash_func;
+        let hot_keys = RefCell::new(HotKeys::new(self.capacity, self.threshold.unwrap_or(1.0 / peers as f64)));
+        let (senders, receiver) = allocator.allocate::<Message<T, D>>(identifier, address);
+        let senders = senders.into_iter().enumerate().map(|(i,x)| LogPusher::new(x, allocator.index(), i, identifier, logging.clone())).collect::<Vec<_>>();
+        let route = move |_: &T, d: &D| {
+            let hash = hash_func(d);
+            let mut hot_keys = hot_keys.borrow_mut();
+            if hot_keys.observe(hash) {
+                hot_keys.rotation += 1;
+                hash.wrapping_add(hot_keys.rotation % split)
+            }
+            else {
+                hash
+            }
+        };
+        (Box::new(ExchangePusher::new(senders, route)), Box::new(LogPuller::new(receiver, allocator.index(), identifier, logging.clone())))
+    }
+}
+
+/// Estimates of the most frequent key hashes, as by the Misra-Gries algorithm, halved periodically
+/// so that the estimates follow changes in the distribution of keys.
+struct HotKeys {
+    counts: HashMap<u64, u64>,
+    capacity: usize,
+    observed: u64,
+    threshold: f64,
+    rotation: u64,
+}
+
+impl HotKeys {
+    const DECAY_PERIOD: u64 = 1 << 16;
+
+    fn new(capacity: usize, threshold: f64) -> Self {
+        HotKeys {
+            counts: HashMap::with_capacity(capacity),
+            capacity,
+            observed: 0,
+            threshold,
+            rotation: 0,
+        }
+    }
+
+    /// Records an occurrence of `hash`, and reports whether it is hot.
+    fn observe(&mut self, hash: u64) -> bool {
+        self.observed += 1;
+        if let Some(count) = self.counts.get_mut(&hash) {
+            *count += 1;
+        }
+        else if self.counts.len() < self.capacity {
+            self.counts.insert(hash, 1);
+        }
+        else {
+            for count in self.counts.values_mut() {
+                *count -= 1;
+            }
+            self.counts.retain(|_, count| *count > 0);
+        }
+
+        if self.observed >= Self::DECAY_PERIOD {
+            self.observed /= 2;
+            for count in self.counts.values_mut() {
+                *count /= 2;
+            }
+            self.counts.retain(|_, count| *count > 0);
+        }
+
+        self.counts.get(&hash).map_or(false, |count| *count as f64 > self.threshold * self.observed as f64)
+    }
+}
 
 
 /// Wraps a `Message<T,D>` pusher to provide a `Push<(T, Content<D>)>`.
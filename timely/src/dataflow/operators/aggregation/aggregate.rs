@@ -6,6 +6,9 @@ use crate::{Data, ExchangeData};
 use crate::dataflow::{Stream, Scope};
 use crate::dataflow::operators::generic::operator::Operator;
 use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::FrontierNotificator;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
 
 /// Generic intra-timestamp aggregation
 ///
@@ -103,3 +106,90 @@ impl<S: Scope, K: ExchangeData+Hash+Eq, V: ExchangeData> Aggregate<S, K, V> for
 
     }
 }
+
+/// Intra-timestamp aggregation keeping its aggregates in managed state.
+///
+/// Behaves as `Aggregate`, but stores the aggregates in `ManagedMap`s obtained from the operator's
+/// state handle rather than in memory, so that the state backend of the scope decides where they live.
+pub trait ManagedAggregate<S: Scope, K: ExchangeData+Hash, V: ExchangeData> {
+    /// Aggregates data of the form `(key, val)`, using user-supplied logic.
+    ///
+    /// The functions `fold`, `emit` and `hash` play the same roles as in `Aggregate::aggregate`.
+    /// Each aggregate lives in managed state from the first record of its key at a time until the
+    /// time is complete.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Inspect};
+    /// use timely::dataflow::operators::aggregation::ManagedAggregate;
+    ///
+    /// timely::example(|scope| {
+    ///
+    ///     (0u64..10).to_stream(scope)
+    ///         .map(|x| (x % 2, x))
+    ///         .managed_aggregate(
+    ///             |_key, val, agg| { *agg += val; },
+    ///             |key, agg: u64| (key, agg),
+    ///             |key| *key as u64
+    ///         )
+    ///         .inspect(|x| assert!(*x == (0, 20) || *x == (1, 25)));
+    /// });
+    /// ```
+    fn managed_aggregate<R: Data, D: Default+FasterValue+FasterRmw+'static, F: Fn(&K, V, &mut D)+'static, E: Fn(K, D)->R+'static, H: Fn(&K)->u64+'static>(
+        &self,
+        fold: F,
+        emit: E,
+        hash: H) -> Stream<S, R> where S::Timestamp: FasterKey;
+}
+
+impl<S: Scope, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData> ManagedAggregate<S, K, V> for Stream<S, (K, V)> {
+
+    fn managed_aggregate<R: Data, D: Default+FasterValue+FasterRmw+'static, F: Fn(&K, V, &mut D)+'static, E: Fn(K, D)->R+'static, H: Fn(&K)->u64+'static>(
+        &self,
+        fold: F,
+        emit: E,
+        hash: H) -> Stream<S, R> where S::Timestamp: FasterKey {
+
+        self.unary_frontier(Exchange::new(move |&(ref k, _)| hash(k)), "ManagedAggregate", |_capability, _info, state_handle| {
+
+            // (time, key) -> aggregate, and time -> keys with an aggregate at that time
+            let mut aggregates = state_handle.get_managed_map::<(S::Timestamp, K), D>("aggregates");
+            let mut keys = state_handle.get_managed_map::<S::Timestamp, Vec<K>>("keys");
+
+            let mut notificator = FrontierNotificator::new();
+            let mut vector = Vec::new();
+
+            move |input, output| {
+
+                // read each input, fold into aggregates
+                while let Some((time, data)) = input.next() {
+                    data.swap(&mut vector);
+                    for (key, val) in vector.drain(..) {
+                        let entry = (time.time().clone(), key);
+                        let mut agg = match aggregates.remove(&entry) {
+                            Some(agg) => agg,
+                            None => {
+                                keys.rmw(entry.0.clone(), vec![entry.1.clone()]);
+                                Default::default()
+                            }
+                        };
+                        fold(&entry.1, val, &mut agg);
+                        aggregates.insert(entry, agg);
+                    }
+                    notificator.notify_at(time.retain());
+                }
+
+                // pop completed aggregates, send along whatever
+                notificator.for_each(&[input.frontier()], |time, _| {
+                    if let Some(time_keys) = keys.remove(time.time()) {
+                        let mut session = output.session(&time);
+                        for key in time_keys {
+                            let agg = aggregates.remove(&(time.time().clone(), key.clone())).expect("Aggregate missing for key");
+                            session.give(emit(key, agg));
+                        }
+                    }
+                });
+            }
+        })
+    }
+}
@@ -0,0 +1,121 @@
+//! Intra-timestamp aggregation pre-aggregating records before they are exchanged.
+use std::hash::Hash;
+use std::collections::HashMap;
+
+use crate::{Data, ExchangeData};
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::operators::Capability;
+use crate::dataflow::operators::generic::operator::Operator;
+use crate::dataflow::channels::pact::Pipeline;
+
+use super::Aggregate;
+
+/// When the combiner sends its partial aggregates on to be exchanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Partial aggregates of a time are sent once the input frontier has passed the time.
+    Frontier,
+    /// As `Frontier`, and also whenever the partial aggregates of a time cover this many keys.
+    MaxKeys(usize),
+    /// Partial aggregates are sent at the end of every invocation of the combiner.
+    EachInvocation,
+}
+
+/// Generic intra-timestamp aggregation with a combiner.
+///
+/// Extension method folding records into partial aggregates on the worker producing them, before
+/// exchanging only the partial aggregates and completing the aggregation as `Aggregate` does.
+pub trait CombinedAggregate<S: Scope, K: ExchangeData+Hash+Eq, V: Data> {
+    /// Aggregates data of the form `(key, val)`, using user-supplied logic.
+    ///
+    /// The function `combine` folds records into partial aggregates of type `P` on the sending worker,
+    /// which are flushed according to `policy`. The functions `merge`, `emit` and `hash` then play the
+    /// roles of `fold`, `emit` and `hash` in `Aggregate::aggregate`, applied to the partial aggregates.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Inspect};
+    /// use timely::dataflow::operators::aggregation::{CombinedAggregate, FlushPolicy};
+    ///
+    /// timely::example(|scope| {
+    ///
+    ///     (0..10).to_stream(scope)
+    ///         .map(|x| (x % 2, x))
+    ///         .combined_aggregate(
+    ///             |_key, val, partial: &mut i32| { *partial += val; },
+    ///             |_key, partial, agg: &mut i32| { *agg += partial; },
+    ///             |key, agg| (key, agg),
+    ///             |key| *key as u64,
+    ///             FlushPolicy::MaxKeys(1000),
+    ///         )
+    ///         .inspect(|x| assert!(*x == (0, 20) || *x == (1, 25)));
+    /// });
+    /// ```
+    fn combined_aggregate<
+        P: ExchangeData+Default,
+        R: Data,
+        D: Default+'static,
+        C: Fn(&K, V, &mut P)+'static,
+        F: Fn(&K, P, &mut D)+'static,
+        E: Fn(K, D)->R+'static,
+        H: Fn(&K)->u64+'static,
+    >(&self, combine: C, merge: F, emit: E, hash: H, policy: FlushPolicy) -> Stream<S, R> where S::Timestamp: Hash+Eq;
+}
+
+impl<S: Scope, K: ExchangeData+Hash+Eq, V: Data> CombinedAggregate<S, K, V> for Stream<S, (K, V)> {
+
+    fn combined_aggregate<
+        P: ExchangeData+Default,
+        R: Data,
+        D: Default+'static,
+        C: Fn(&K, V, &mut P)+'static,
+        F: Fn(&K, P, &mut D)+'static,
+        E: Fn(K, D)->R+'static,
+        H: Fn(&K)->u64+'static,
+    >(&self, combine: C, merge: F, emit: E, hash: H, policy: FlushPolicy) -> Stream<S, R> where S::Timestamp: Hash+Eq {
+
+        self.unary_frontier(Pipeline, "Combine", |_capability, _info, _state_handle| {
+
+            // time -> (capability, key -> partial aggregate)
+            let mut partials: HashMap<S::Timestamp, (Capability<S::Timestamp>, HashMap<K, P>)> = HashMap::new();
+            let mut vector = Vec::new();
+
+            move |input, output| {
+
+                // fold records into partial aggregates, flushing times that grow too large.
+                while let Some((time, data)) = input.next() {
+                    data.swap(&mut vector);
+                    let partial_time = time.time().clone();
+                    let flush = {
+                        let (_, partial) = partials.entry(partial_time.clone()).or_insert_with(|| (time.retain(), HashMap::new()));
+                        for (key, val) in vector.drain(..) {
+                            let agg = partial.entry(key.clone()).or_insert_with(Default::default);
+                            combine(&key, val, agg);
+                        }
+                        match policy {
+                            FlushPolicy::MaxKeys(max_keys) => partial.len() >= max_keys,
+                            _ => false,
+                        }
+                    };
+                    if flush {
+                        let (capability, partial) = partials.remove(&partial_time).unwrap();
+                        output.session(&capability).give_iterator(partial.into_iter());
+                    }
+                }
+
+                // flush times the frontier has passed, or every time if flushing each invocation.
+                let frontier = input.frontier();
+                let complete = partials
+                    .keys()
+                    .filter(|time| policy == FlushPolicy::EachInvocation || !frontier.less_equal(time))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for time in complete {
+                    let (capability, partial) = partials.remove(&time).unwrap();
+                    output.session(&capability).give_iterator(partial.into_iter());
+                }
+            }
+        })
+        .aggregate(move |key, partial, agg| merge(key, partial, agg), emit, hash)
+    }
+}
@@ -10,9 +10,22 @@
 //!
 //! The two methods are often combined, using first `Aggregate` to reduce the volume of information, and then
 //! `StateMachine` to track an accumulation across timestamps.
+//!
+//! `ManagedAggregate` and `ManagedStateMachine` behave as their counterparts, but keep their state in
+//! managed maps obtained from the operator's state handle, so that it is held by the scope's state backend.
+//!
+//! `CombinedAggregate` folds records into partial aggregates before exchanging them, and completes the
+//! aggregation of the partial aggregates with `Aggregate`.
+//!
+//! `SkewedAggregate` exchanges records with a pact spreading hot keys over several workers, and merges the
+//! partial aggregates of each key with `Aggregate`.
 
-pub use self::aggregate::Aggregate;
-pub use self::state_machine::StateMachine;
+pub use self::aggregate::{Aggregate, ManagedAggregate};
+pub use self::state_machine::{StateMachine, ManagedStateMachine};
+pub use self::combine::{CombinedAggregate, FlushPolicy};
+pub use self::skewed::SkewedAggregate;
 
 pub mod state_machine;
 pub mod aggregate;
+pub mod combine;
+pub mod skewed;
@@ -0,0 +1,99 @@
+//! Intra-timestamp aggregation robust to keys that dominate the input.
+use std::hash::Hash;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::{Data, ExchangeData};
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::operators::generic::operator::Operator;
+use crate::dataflow::channels::pact::SkewExchange;
+
+use super::Aggregate;
+
+/// Generic intra-timestamp aggregation spreading hot keys over several workers.
+///
+/// Extension method aggregating records in two phases. Records are first exchanged with a
+/// `SkewExchange` pact, which may send the records of a hot key to several workers, and folded
+/// into partial aggregates there. The partial aggregates are then exchanged by key and merged, so
+/// that each key is still produced once per time.
+pub trait SkewedAggregate<S: Scope, K: ExchangeData+Hash+Eq, V: ExchangeData> {
+    /// Aggregates data of the form `(key, val)`, using user-supplied logic.
+    ///
+    /// The function `fold` folds records into partial aggregates of type `P`, and `merge` folds the
+    /// partial aggregates of a key into its aggregate, from which `emit` produces the output. The
+    /// function `hash` is used by both phases, and `split` is the number of workers each hot key is
+    /// spread over.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Inspect};
+    /// use timely::dataflow::operators::aggregation::SkewedAggregate;
+    ///
+    /// timely::example(|scope| {
+    ///
+    ///     (0..10).to_stream(scope)
+    ///         .map(|x| (if x < 8 { 0 } else { 1 }, x))
+    ///         .skewed_aggregate(
+    ///             |_key, val, partial: &mut i32| { *partial += val; },
+    ///             |_key, partial, agg: &mut i32| { *agg += partial; },
+    ///             |key, agg| (key, agg),
+    ///             |key| *key as u64,
+    ///             4,
+    ///         )
+    ///         .inspect(|x| assert!(*x == (0, 28) || *x == (1, 17)));
+    /// });
+    /// ```
+    fn skewed_aggregate<
+        P: ExchangeData+Default,
+        R: Data,
+        D: Default+'static,
+        F: Fn(&K, V, &mut P)+'static,
+        M: Fn(&K, P, &mut D)+'static,
+        E: Fn(K, D)->R+'static,
+        H: Fn(&K)->u64+'static,
+    >(&self, fold: F, merge: M, emit: E, hash: H, split: usize) -> Stream<S, R> where S::Timestamp: Hash+Eq;
+}
+
+impl<S: Scope, K: ExchangeData+Hash+Eq, V: ExchangeData> SkewedAggregate<S, K, V> for Stream<S, (K, V)> {
+
+    fn skewed_aggregate<
+        P: ExchangeData+Default,
+        R: Data,
+        D: Default+'static,
+        F: Fn(&K, V, &mut P)+'static,
+        M: Fn(&K, P, &mut D)+'static,
+        E: Fn(K, D)->R+'static,
+        H: Fn(&K)->u64+'static,
+    >(&self, fold: F, merge: M, emit: E, hash: H, split: usize) -> Stream<S, R> where S::Timestamp: Hash+Eq {
+
+        let hash = Rc::new(hash);
+        let skew_hash = hash.clone();
+        let pact = SkewExchange::new(move |&(ref k, _): &(K, V)| skew_hash(k)).split(split);
+
+        // phase one: partial aggregates of the keys each worker received.
+        let mut partials = HashMap::new();
+        let mut vector = Vec::new();
+        self.unary_notify(pact, "SkewedAggregate", vec![], move |input, output, notificator, _state_handle| {
+
+            // read each input, fold into partial aggregates.
+            input.for_each(|time, data| {
+                data.swap(&mut vector);
+                let agg_time = partials.entry(time.time().clone()).or_insert_with(HashMap::new);
+                for (key, val) in vector.drain(..) {
+                    let agg = agg_time.entry(key.clone()).or_insert_with(Default::default);
+                    fold(&key, val, agg);
+                }
+                notificator.notify_at(time.retain());
+            });
+
+            // pop completed partial aggregates, send them on to be merged.
+            notificator.for_each(|time,_,_| {
+                if let Some(partials) = partials.remove(time.time()) {
+                    output.session(&time).give_iterator(partials.into_iter());
+                }
+            });
+        })
+        // phase two: merge the partial aggregates of each key on a single worker.
+        .aggregate(move |key, partial, agg| merge(key, partial, agg), emit, move |key| hash(key))
+    }
+}
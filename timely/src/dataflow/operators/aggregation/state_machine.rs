@@ -6,6 +6,9 @@ use crate::{Data, ExchangeData};
 use crate::dataflow::{Stream, Scope};
 use crate::dataflow::operators::generic::operator::Operator;
 use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::FrontierNotificator;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
 
 /// Generic state-transition machinery: each key has a state, and receives a sequence of events.
 /// Events are applied in time-order, but no other promises are made. Each state transition can
@@ -111,3 +114,134 @@ impl<S: Scope, K: ExchangeData+Hash+Eq, V: ExchangeData> StateMachine<S, K, V> f
         })
     }
 }
+
+/// Provides the `managed_state_machine` method.
+///
+/// Behaves as `StateMachine`, but keeps the per-key states and the events buffered for future times
+/// in `ManagedMap`s obtained from the operator's state handle.
+pub trait ManagedStateMachine<S: Scope, K: ExchangeData+Hash+Eq, V: ExchangeData> {
+    /// Tracks a state for each presented key in managed state, using user-supplied state transition logic.
+    ///
+    /// The functions `fold` and `hash` play the same roles as in `StateMachine::state_machine`.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Inspect};
+    /// use timely::dataflow::operators::aggregation::ManagedStateMachine;
+    ///
+    /// timely::example(|scope| {
+    ///
+    ///     // these results happen to be right, but aren't guaranteed.
+    ///     // the system is at liberty to re-order within a timestamp.
+    ///     let result = vec![(0,0), (0,2), (0,6), (0,12), (0,20),
+    ///                       (1,1), (1,4), (1,9), (1,16), (1,25)];
+    ///
+    ///         (0u64..10).to_stream(scope)
+    ///                .map(|x| (x % 2, x))
+    ///                .managed_state_machine(
+    ///                    |_key, val, agg: &mut u64| { *agg += val; (false, Some((*_key, *agg))) },
+    ///                    |key| *key as u64
+    ///                )
+    ///                .inspect(move |x| assert!(result.contains(x)));
+    /// });
+    /// ```
+    ///
+    /// Events are folded in the order of their times, including those buffered for a time that has
+    /// since completed, before events that arrive at later times.
+    ///
+    /// ```
+    /// use timely::dataflow::operators::{UnorderedInput, Capture};
+    /// use timely::dataflow::operators::aggregation::ManagedStateMachine;
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::state::backends::InMemoryBackend;
+    ///
+    /// let captured = timely::execute_directly(|worker| {
+    ///     let ((mut input, capability), captured) = worker.dataflow::<u64,_,_,InMemoryBackend>(|scope, _| {
+    ///         let (input, stream) = scope.new_unordered_input();
+    ///         let captured = stream
+    ///             .managed_state_machine(|_key: &u64, val: char, seen: &mut String| { seen.push(val); (false, Some(seen.clone())) }, |key| *key)
+    ///             .capture();
+    ///         (input, captured)
+    ///     });
+    ///
+    ///     // the event at time 1 arrives while time 0 is incomplete, and is buffered.
+    ///     let later = capability.delayed(&2);
+    ///     input.session(capability.delayed(&1)).give((0, 'a'));
+    ///     for _ in 0 .. 10 { worker.step(); }
+    ///
+    ///     // the event at time 2 arrives once time 1 is complete, and must follow the one at time 1.
+    ///     drop(capability);
+    ///     worker.step();
+    ///     input.session(later).give((0, 'b'));
+    ///     captured
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(1, vec!["a".to_string()]), (2, vec!["ab".to_string()])]);
+    /// ```
+    fn managed_state_machine<
+        R: Data,                                                    // output type
+        D: Default+FasterValue+FasterRmw+'static,                   // per-key state (data)
+        I: IntoIterator<Item=R>,                                    // type of output iterator
+        F: Fn(&K, V, &mut D)->(bool, I)+'static,                    // state update logic
+        H: Fn(&K)->u64+'static,                                     // "hash" function for keys
+    >(&self, fold: F, hash: H) -> Stream<S, R> where S::Timestamp : FasterKey;
+}
+
+impl<S: Scope, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData+FasterValue> ManagedStateMachine<S, K, V> for Stream<S, (K, V)> {
+    fn managed_state_machine<
+            R: Data,                                                    // output type
+            D: Default+FasterValue+FasterRmw+'static,                   // per-key state (data)
+            I: IntoIterator<Item=R>,                                    // type of output iterator
+            F: Fn(&K, V, &mut D)->(bool, I)+'static,                    // state update logic
+            H: Fn(&K)->u64+'static,                                     // "hash" function for keys
+        >(&self, fold: F, hash: H) -> Stream<S, R> where S::Timestamp : FasterKey {
+
+        self.unary_frontier(Exchange::new(move |&(ref k, _)| hash(k)), "ManagedStateMachine", |_capability, _info, state_handle| {
+
+            let mut pending = state_handle.get_managed_map::<S::Timestamp, Vec<(K, V)>>("pending");   // times -> (key, val)
+            let mut states = state_handle.get_managed_map::<K, D>("states");                        // keys -> state
+
+            let mut notificator = FrontierNotificator::new();
+            let mut vector = Vec::new();
+
+            move |input, output| {
+
+                // go through each time with data, process each (key, val) pair.
+                notificator.for_each(&[input.frontier()], |time, _| {
+                    if let Some(pend) = pending.remove(time.time()) {
+                        let mut session = output.session(&time);
+                        for (key, val) in pend {
+                            let mut state = states.remove(&key).unwrap_or_default();
+                            let (remove, output) = fold(&key, val, &mut state);
+                            if !remove { states.insert(key, state); }
+                            session.give_iterator(output.into_iter());
+                        }
+                    }
+                });
+
+                // stash each input and request a notification when ready
+                let frontier = input.frontier;
+                while let Some((time, data)) = input.handle.next() {
+
+                    data.swap(&mut vector);
+
+                    // stash if not time yet
+                    if frontier.less_than(time.time()) {
+                        pending.rmw(time.time().clone(), vector.drain(..).collect());
+                        notificator.notify_at(time.retain());
+                    }
+                    else {
+                        // else we can process immediately
+                        let mut session = output.session(&time);
+                        for (key, val) in vector.drain(..) {
+                            let mut state = states.remove(&key).unwrap_or_default();
+                            let (remove, output) = fold(&key, val, &mut state);
+                            if !remove { states.insert(key, state); }
+                            session.give_iterator(output.into_iter());
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
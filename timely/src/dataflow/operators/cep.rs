@@ -0,0 +1,342 @@
+//! Complex event processing: matching declarative patterns against the records of each key.
+//!
+//! A `Pattern` is a sequence of stages, each accepting records satisfying a predicate, a number of
+//! times within some bounds. The pattern is matched as a non-deterministic automaton: each key holds
+//! the partial matches, or runs, in progress, and each record of the key may extend, advance, or start
+//! runs. Runs are held in managed state, and the records of each time are processed once the time is
+//! complete, in time order, and in order of arrival within a time.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::ExchangeData;
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::Capability;
+use crate::dataflow::operators::generic::operator::Operator;
+
+use faster_rs::{FasterKey, FasterValue};
+
+/// How the records of a stage may be separated from the records preceding them in a match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Contiguity {
+    /// The records must immediately follow each other among the records of the key.
+    Strict,
+    /// Records of the key not accepted by the stage may come in between.
+    Relaxed,
+}
+
+/// Which runs are discarded once a match has been produced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipStrategy {
+    /// No runs are discarded, and every match is produced.
+    NoSkip,
+    /// Runs starting with the same record as the match are discarded.
+    SkipToNext,
+    /// All runs of the key are discarded, so that matches do not overlap. Among the matches completed by
+    /// a record, runs that accepted the preceding records are preferred to runs that skipped them.
+    SkipPastLastEvent,
+}
+
+struct Stage<V> {
+    name: String,
+    predicate: Rc<Fn(&V)->bool>,
+    contiguity: Contiguity,
+    min: usize,
+    max: Option<usize>,
+}
+
+/// A sequence of stages to match against the records of each key.
+pub struct Pattern<V> {
+    stages: Vec<Stage<V>>,
+    within: Option<u64>,
+    skip: SkipStrategy,
+}
+
+impl<V> Pattern<V> {
+    /// Starts a pattern with a stage accepting one record satisfying `predicate`.
+    ///
+    /// Repetitions of the first stage have relaxed contiguity.
+    pub fn begin(name: &str, predicate: impl Fn(&V)->bool+'static) -> Self {
+        Pattern {
+            stages: Vec::new(),
+            within: None,
+            skip: SkipStrategy::NoSkip,
+        }
+        .stage(name, predicate, Contiguity::Relaxed)
+    }
+
+    /// Adds a stage accepting one record satisfying `predicate`, immediately after those of the previous stage.
+    pub fn next(self, name: &str, predicate: impl Fn(&V)->bool+'static) -> Self {
+        self.stage(name, predicate, Contiguity::Strict)
+    }
+
+    /// Adds a stage accepting one record satisfying `predicate`, at any point after those of the previous stage.
+    pub fn followed_by(self, name: &str, predicate: impl Fn(&V)->bool+'static) -> Self {
+        self.stage(name, predicate, Contiguity::Relaxed)
+    }
+
+    /// Requires the last stage to accept at least `min` and at most `max` records.
+    pub fn times(mut self, min: usize, max: usize) -> Self {
+        assert!(1 <= min && min <= max, "Pattern stages must accept a positive range of records");
+        let stage = self.stages.last_mut().unwrap();
+        stage.min = min;
+        stage.max = Some(max);
+        self
+    }
+
+    /// Requires the last stage to accept one or more records.
+    pub fn one_or_more(mut self) -> Self {
+        let stage = self.stages.last_mut().unwrap();
+        stage.min = 1;
+        stage.max = None;
+        self
+    }
+
+    /// Requires the last record of a match to be at most `duration` after its first.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Delay, Map, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::cep::{Pattern, PatternMatch};
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let pattern = Pattern::begin("low", |x: &u64| *x < 10)
+    ///         .followed_by("high", |x: &u64| *x >= 50)
+    ///         .within(u64::max_value());
+    ///
+    ///     vec![(0u64, 5u64), (100, 60)]
+    ///         .to_stream(scope)
+    ///         .delay(|x, _| x.0)
+    ///         .map(|(_, reading)| (0u64, reading))
+    ///         .match_pattern(pattern, |key| *key)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(100, vec![(0, vec![("low".to_string(), 5), ("high".to_string(), 60)])])]);
+    /// ```
+    pub fn within(mut self, duration: u64) -> Self {
+        self.within = Some(duration);
+        self
+    }
+
+    /// Sets the runs discarded once a match has been produced.
+    pub fn skip(mut self, skip: SkipStrategy) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    fn stage(mut self, name: &str, predicate: impl Fn(&V)->bool+'static, contiguity: Contiguity) -> Self {
+        self.stages.push(Stage {
+            name: name.to_owned(),
+            predicate: Rc::new(predicate),
+            contiguity,
+            min: 1,
+            max: Some(1),
+        });
+        self
+    }
+}
+
+/// A partial match of a pattern.
+#[derive(Clone, Serialize, Deserialize)]
+struct Run<V> {
+    /// The stage accepting the latest record.
+    stage: usize,
+    /// The number of records the stage has accepted.
+    count: usize,
+    /// The time of the first record.
+    start: u64,
+    /// An identifier of the first record, shared by runs it started.
+    origin: u64,
+    /// The stage and value of each record accepted.
+    events: Vec<(usize, V)>,
+}
+
+impl<V> Pattern<V> {
+
+    fn is_complete<W>(&self, run: &Run<W>) -> bool {
+        run.stage + 1 == self.stages.len() && run.count >= self.stages[run.stage].min
+    }
+
+    fn can_extend<W>(&self, run: &Run<W>) -> bool {
+        let stage = &self.stages[run.stage];
+        stage.max.map_or(true, |max| run.count < max)
+    }
+
+    fn can_advance<W>(&self, run: &Run<W>) -> bool {
+        run.count >= self.stages[run.stage].min && run.stage + 1 < self.stages.len()
+    }
+
+    /// Applies the record `value` at `time` to the runs of a key, returning the matches completed.
+    fn step(&self, runs: &mut Vec<Run<V>>, value: &V, time: u64, origin: u64) -> Vec<Vec<(String, V)>> where V: Clone {
+
+        let mut ignoring = Vec::new();
+        let mut candidates = Vec::new();
+
+        for run in runs.drain(..) {
+            if self.within.map_or(false, |within| time - run.start > within) {
+                continue;
+            }
+
+            let stage = &self.stages[run.stage];
+            if self.can_extend(&run) && (stage.predicate)(value) {
+                let mut extended = run.clone();
+                extended.count += 1;
+                extended.events.push((run.stage, value.clone()));
+                candidates.push(extended);
+            }
+            if self.can_advance(&run) && (self.stages[run.stage + 1].predicate)(value) {
+                let mut advanced = run.clone();
+                advanced.stage += 1;
+                advanced.count = 1;
+                advanced.events.push((advanced.stage, value.clone()));
+                candidates.push(advanced);
+            }
+
+            // the run may ignore the record if a relaxed stage could accept its next record.
+            let relaxed =
+                (self.can_extend(&run) && stage.contiguity == Contiguity::Relaxed) ||
+                (self.can_advance(&run) && self.stages[run.stage + 1].contiguity == Contiguity::Relaxed);
+            if relaxed {
+                ignoring.push(run);
+            }
+        }
+
+        if (self.stages[0].predicate)(value) {
+            candidates.push(Run { stage: 0, count: 1, start: time, origin, events: vec![(0, value.clone())] });
+        }
+
+        // runs accepting the record come first, so that longer matches are preferred when skipping.
+        let mut next_runs = Vec::new();
+        let mut matches = Vec::new();
+        let mut matched_origins = Vec::new();
+        for run in candidates {
+            if self.is_complete(&run) {
+                let first_of_origin = !matched_origins.contains(&run.origin);
+                if self.skip == SkipStrategy::NoSkip || (first_of_origin && (self.skip == SkipStrategy::SkipToNext || matches.is_empty())) {
+                    matched_origins.push(run.origin);
+                    matches.push(run.events.iter().map(|(stage, value)| (self.stages[*stage].name.clone(), value.clone())).collect());
+                }
+            }
+            if self.can_extend(&run) || !self.is_complete(&run) {
+                next_runs.push(run);
+            }
+        }
+
+        next_runs.extend(ignoring);
+
+        match self.skip {
+            SkipStrategy::NoSkip => { },
+            SkipStrategy::SkipToNext => next_runs.retain(|run| !matched_origins.contains(&run.origin)),
+            SkipStrategy::SkipPastLastEvent => if !matches.is_empty() { next_runs.clear(); },
+        }
+
+        *runs = next_runs;
+        matches
+    }
+}
+
+/// Extension trait for `Stream`.
+pub trait PatternMatch<G: Scope<Timestamp=u64>, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData+FasterValue> {
+    /// Produces a `(key, match)` pair for each match of `pattern` among the records of a key, where
+    /// `match` lists the name of the stage accepting each record of the match, and the record's value.
+    ///
+    /// Each match is produced at the time of its last record. The function `hash` routes keys to workers.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Delay, Map, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::cep::{Pattern, PatternMatch};
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let pattern = Pattern::begin("low", |x: &u64| *x < 10)
+    ///         .followed_by("high", |x: &u64| *x >= 50)
+    ///         .within(5);
+    ///
+    ///     vec![(0u64, 5u64), (1, 20), (2, 60), (3, 8), (10, 70)]
+    ///         .to_stream(scope)
+    ///         .delay(|x, _| x.0)
+    ///         .map(|(_, reading)| (0u64, reading))
+    ///         .match_pattern(pattern, |key| *key)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(2, vec![(0, vec![("low".to_string(), 5), ("high".to_string(), 60)])])]);
+    /// ```
+    fn match_pattern<H: Fn(&K)->u64+'static>(&self, pattern: Pattern<V>, hash: H) -> Stream<G, (K, Vec<(String, V)>)>;
+}
+
+impl<G: Scope<Timestamp=u64>, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData+FasterValue> PatternMatch<G, K, V> for Stream<G, (K, V)> {
+
+    fn match_pattern<H: Fn(&K)->u64+'static>(&self, pattern: Pattern<V>, hash: H) -> Stream<G, (K, Vec<(String, V)>)> {
+
+        self.unary_frontier(Exchange::new(move |&(ref k, _)| hash(k)), "PatternMatch", move |_capability, _info, state_handle| {
+
+            // time -> records not yet processed
+            let mut pending = state_handle.get_managed_map::<u64, Vec<(K, V)>>("pending");
+            // key -> runs in progress
+            let mut runs = state_handle.get_managed_map::<K, Vec<Run<V>>>("runs");
+            // expiry time -> keys with runs that can no longer complete from then on
+            let mut expirations = state_handle.get_managed_map::<u64, Vec<K>>("expirations");
+            let mut expiration_times = BTreeSet::new();
+
+            // a capability for each time with pending records
+            let mut capabilities: BTreeMap<u64, Capability<u64>> = BTreeMap::new();
+            let mut origins = 0;
+
+            let mut vector = Vec::new();
+
+            move |input, output| {
+
+                while let Some((time, data)) = input.next() {
+                    data.swap(&mut vector);
+                    pending.rmw(*time.time(), vector.drain(..).collect());
+                    capabilities.entry(*time.time()).or_insert_with(|| time.retain());
+                }
+
+                // process complete times in order.
+                while let Some(time) = capabilities.keys().next().cloned().filter(|time| !input.frontier().less_equal(time)) {
+                    let capability = capabilities.remove(&time).unwrap();
+                    let mut session = output.session(&capability);
+                    for (key, value) in pending.remove(&time).unwrap_or_default() {
+                        let origin = origins;
+                        origins += 1;
+                        let mut key_runs = runs.remove(&key).unwrap_or_default();
+                        for found in pattern.step(&mut key_runs, &value, time, origin) {
+                            session.give((key.clone(), found));
+                        }
+                        if !key_runs.is_empty() {
+                            // runs started by this record are the only ones not yet listed under their expiry.
+                            if let Some(within) = pattern.within {
+                                if key_runs.iter().any(|run| run.origin == origin) {
+                                    let expiry = time.saturating_add(within).saturating_add(1);
+                                    expirations.rmw(expiry, vec![key.clone()]);
+                                    expiration_times.insert(expiry);
+                                }
+                            }
+                            runs.insert(key, key_runs);
+                        }
+                    }
+                }
+
+                // discard runs once no record can arrive in time to complete them.
+                while let Some(expiry) = expiration_times.iter().next().cloned().filter(|expiry| !input.frontier().less_than(expiry)) {
+                    expiration_times.remove(&expiry);
+                    let within = pattern.within.unwrap();
+                    for key in expirations.remove(&expiry).unwrap_or_default() {
+                        if let Some(mut key_runs) = runs.remove(&key) {
+                            key_runs.retain(|run| run.start.saturating_add(within).saturating_add(1) > expiry);
+                            if !key_runs.is_empty() {
+                                runs.insert(key, key_runs);
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
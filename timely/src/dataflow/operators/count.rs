@@ -7,6 +7,9 @@ use crate::Data;
 use crate::dataflow::channels::pact::Pipeline;
 use crate::dataflow::{Stream, Scope};
 use crate::dataflow::operators::generic::operator::Operator;
+use crate::dataflow::operators::FrontierNotificator;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
 
 /// Accumulates records within a timestamp.
 pub trait Accumulate<G: Scope, D: Data> {
@@ -68,3 +71,72 @@ impl<G: Scope, D: Data> Accumulate<G, D> for Stream<G, D> {
         })
     }
 }
+
+/// Accumulates records within a timestamp, keeping the accumulations in managed state.
+pub trait ManagedAccumulate<G: Scope, D: Data> where G::Timestamp: FasterKey {
+    /// Accumulates records within a timestamp, as `Accumulate::accumulate` does, storing each
+    /// accumulation in a `ManagedMap` of the operator's state handle until its time is complete.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, ManagedAccumulate, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0..10).to_stream(scope)
+    ///            .managed_accumulate(0, |sum, data| { for &x in data.iter() { *sum += x; } })
+    ///            .capture()
+    /// });
+    ///
+    /// let extracted = captured.extract();
+    /// assert_eq!(extracted, vec![(0, vec![45])]);
+    /// ```
+    fn managed_accumulate<A: Data+FasterValue+FasterRmw>(&self, default: A, logic: impl Fn(&mut A, RefOrMut<Vec<D>>)+'static) -> Stream<G, A>;
+    /// Counts the number of records observed at each time, keeping the counts in managed state.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, ManagedAccumulate, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0..10).to_stream(scope)
+    ///            .managed_count()
+    ///            .capture()
+    /// });
+    ///
+    /// let extracted = captured.extract();
+    /// assert_eq!(extracted, vec![(0, vec![10])]);
+    /// ```
+    fn managed_count(&self) -> Stream<G, usize> {
+        self.managed_accumulate(0, |sum, data| *sum += data.len())
+    }
+}
+
+impl<G: Scope, D: Data> ManagedAccumulate<G, D> for Stream<G, D> where G::Timestamp: FasterKey {
+    fn managed_accumulate<A: Data+FasterValue+FasterRmw>(&self, default: A, logic: impl Fn(&mut A, RefOrMut<Vec<D>>)+'static) -> Stream<G, A> {
+
+        self.unary_frontier(Pipeline, "ManagedAccumulate", |_capability, _info, state_handle| {
+
+            let mut accums = state_handle.get_managed_map::<G::Timestamp, A>("accums");
+            let mut notificator = FrontierNotificator::new();
+
+            move |input, output| {
+                while let Some((time, data)) = input.next() {
+                    let mut accum = accums.remove(time.time()).unwrap_or_else(|| default.clone());
+                    logic(&mut accum, data);
+                    accums.insert(time.time().clone(), accum);
+                    notificator.notify_at(time.retain());
+                }
+
+                notificator.for_each(&[input.frontier()], |time, _| {
+                    if let Some(accum) = accums.remove(time.time()) {
+                        output.session(&time).give(accum);
+                    }
+                });
+            }
+        })
+    }
+}
@@ -0,0 +1,321 @@
+//! Removal of records whose key was recently seen.
+//!
+//! Records are exchanged by key, and each worker remembers the keys it has seen in managed state.
+//! A key seen at time `t` is remembered until the input frontier has passed `t` advanced by the
+//! expiry horizon, and records with that key at earlier times are dropped.
+//!
+//! Records are held in managed state until the input frontier passes their time, and times are then
+//! processed in order, so that the record kept for a key is one at the earliest time it was seen,
+//! whatever the order records arrive in. Among records of a key at the same time, the first to
+//! arrive is kept.
+//!
+//! Optionally, Bloom filters of the remembered keys are kept in memory, so that keys never seen
+//! before are recognized without a state lookup. As keys cannot be removed from a Bloom filter, the
+//! filters are rotated through generations, and a generation is discarded once all its keys expire.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::ExchangeData;
+use crate::order::PartialOrder;
+use crate::progress::Timestamp;
+use crate::progress::timestamp::PathSummary;
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::Capability;
+use crate::dataflow::operators::generic::operator::Operator;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+
+/// The remembered state of a key.
+#[derive(Clone, Serialize, Deserialize)]
+struct Seen<T> {
+    /// The time from which records with the key are no longer duplicates, if any.
+    expiry: Option<T>,
+    /// The Bloom filter generation holding the key.
+    generation: u64,
+}
+
+impl<T> FasterRmw for Seen<T> where Seen<T>: FasterValue {
+    fn rmw(&self, modification: Self) -> Self {
+        modification
+    }
+}
+
+/// Extension trait for `Stream`.
+pub trait Dedup<G: Scope, D: ExchangeData+FasterValue> where G::Timestamp: FasterKey {
+    /// Drops records whose key, as extracted by `key`, was seen in a record at a time that `horizon`
+    /// does not advance beyond the record's time. The function `hash` routes keys to workers.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Delay, Dedup, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     vec![(0u64, 'a'), (1, 'a'), (2, 'b'), (7, 'a')]
+    ///         .to_stream(scope)
+    ///         .delay(|x, _| x.0)
+    ///         .dedup(|x| x.1, |key| *key as u64, 5)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, vec![(0, 'a')]), (2, vec![(2, 'b')]), (7, vec![(7, 'a')])]);
+    /// ```
+    ///
+    /// A record at an earlier time is kept even when it arrives after a duplicate at a later time.
+    /// ```
+    /// use timely::dataflow::operators::{UnorderedInput, Dedup, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::state::backends::InMemoryBackend;
+    ///
+    /// let captured = timely::execute_directly(|worker| {
+    ///     let ((mut input, capability), captured) = worker.dataflow::<u64,_,_,InMemoryBackend>(|scope, _| {
+    ///         let (input, stream) = scope.new_unordered_input();
+    ///         (input, stream.dedup(|x: &char| *x, |key| *key as u64, 5).capture())
+    ///     });
+    ///     input.session(capability.delayed(&3)).give('a');
+    ///     worker.step();
+    ///     input.session(capability.delayed(&1)).give('a');
+    ///     captured
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(1, vec!['a'])]);
+    /// ```
+    fn dedup<K, E, H>(&self, key: E, hash: H, horizon: <G::Timestamp as Timestamp>::Summary) -> Stream<G, D>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        E: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static;
+
+    /// Drops records as `dedup` does, consulting Bloom filters before looking keys up in state.
+    ///
+    /// Each generation of Bloom filter holds up to `keys_per_filter` keys, with a false positive
+    /// rate of about `false_positive_rate`.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Dedup, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..100)
+    ///         .to_stream(scope)
+    ///         .dedup_with_bloom(|x| x % 10, |key| *key, 5, 4, 0.01)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, (0..10).collect::<Vec<_>>())]);
+    /// ```
+    fn dedup_with_bloom<K, E, H>(&self, key: E, hash: H, horizon: <G::Timestamp as Timestamp>::Summary, keys_per_filter: usize, false_positive_rate: f64) -> Stream<G, D>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        E: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static;
+}
+
+impl<G: Scope, D: ExchangeData+FasterValue> Dedup<G, D> for Stream<G, D> where G::Timestamp: FasterKey {
+
+    fn dedup<K, E, H>(&self, key: E, hash: H, horizon: <G::Timestamp as Timestamp>::Summary) -> Stream<G, D>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        E: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        dedup_core(self, key, hash, horizon, None, "Dedup")
+    }
+
+    fn dedup_with_bloom<K, E, H>(&self, key: E, hash: H, horizon: <G::Timestamp as Timestamp>::Summary, keys_per_filter: usize, false_positive_rate: f64) -> Stream<G, D>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        E: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        assert!(keys_per_filter > 0, "Bloom filters must hold at least one key");
+        assert!(0.0 < false_positive_rate && false_positive_rate < 1.0, "Bloom filter false positive rate must be in (0, 1)");
+        dedup_core(self, key, hash, horizon, Some((keys_per_filter, false_positive_rate)), "BloomDedup")
+    }
+}
+
+fn dedup_core<G, D, K, E, H>(
+    stream: &Stream<G, D>,
+    key: E,
+    hash: H,
+    horizon: <G::Timestamp as Timestamp>::Summary,
+    bloom: Option<(usize, f64)>,
+    name: &str) -> Stream<G, D>
+where
+    G: Scope,
+    G::Timestamp: FasterKey,
+    D: ExchangeData+FasterValue,
+    K: ExchangeData+Hash+Eq+FasterKey,
+    E: Fn(&D)->K+'static,
+    H: Fn(&K)->u64+'static,
+{
+    let key = Rc::new(key);
+    let exchange_key = key.clone();
+    let exchange = Exchange::new(move |datum: &D| hash(&exchange_key(datum)));
+
+    stream.unary_frontier(exchange, name, move |_capability, _info, state_handle| {
+
+        // time -> records not yet processed
+        let mut pending = state_handle.get_managed_map::<G::Timestamp, Vec<D>>("pending");
+        // key -> expiry time and filter generation of each remembered key
+        let mut seen = state_handle.get_managed_map::<K, Seen<G::Timestamp>>("seen");
+        // expiry time -> keys remembered until then
+        let mut expiring = state_handle.get_managed_map::<G::Timestamp, Vec<K>>("expiring");
+        let mut expiry_times = BTreeSet::new();
+
+        // a capability for each time with pending records
+        let mut capabilities: BTreeMap<G::Timestamp, Capability<G::Timestamp>> = BTreeMap::new();
+
+        let mut filters = bloom.map(|(keys_per_filter, false_positive_rate)| BloomGenerations::new(keys_per_filter, false_positive_rate));
+
+        let mut vector = Vec::new();
+
+        move |input, output| {
+
+            while let Some((time, data)) = input.next() {
+                data.swap(&mut vector);
+                pending.rmw(time.time().clone(), vector.drain(..).collect());
+                capabilities.entry(time.time().clone()).or_insert_with(|| time.retain());
+            }
+
+            // process complete times in order.
+            let complete = capabilities.keys().filter(|time| !input.frontier().less_equal(time)).cloned().collect::<Vec<_>>();
+            for time in complete {
+                let capability = capabilities.remove(&time).unwrap();
+                let mut session = output.session(&capability);
+                for datum in pending.remove(&time).unwrap_or_default() {
+                    let datum_key = key(&datum);
+
+                    // a key absent from all filters was never seen, and need not be looked up.
+                    let maybe_seen = filters.as_ref().map(|filters| filters.contains(&datum_key)).unwrap_or(true);
+                    let previous = if maybe_seen { seen.get(&datum_key) } else { None };
+                    let duplicate = previous.as_ref().map_or(false, |previous| match previous.expiry {
+                        Some(ref expiry) => !expiry.less_equal(&time),
+                        None => true,
+                    });
+
+                    if !duplicate {
+                        if let (Some(previous), Some(filters)) = (previous, filters.as_mut()) {
+                            filters.release(previous.generation);
+                        }
+                        let expiry = horizon.results_in(&time);
+                        let generation = filters.as_mut().map(|filters| filters.insert(&datum_key)).unwrap_or(0);
+                        if let Some(ref expiry) = expiry {
+                            // a key may be listed more than once; expiry checks it is still remembered until then.
+                            expiring.rmw(expiry.clone(), vec![datum_key.clone()]);
+                            expiry_times.insert(expiry.clone());
+                        }
+                        seen.insert(datum_key, Seen { expiry, generation });
+                        session.give(datum);
+                    }
+                }
+            }
+
+            // forget keys once no record can arrive before their expiry time.
+            let expired = expiry_times.iter().filter(|expiry| !input.frontier().less_than(expiry)).cloned().collect::<Vec<_>>();
+            for expiry in expired {
+                expiry_times.remove(&expiry);
+                for expired_key in expiring.remove(&expiry).unwrap_or_default() {
+                    let current = seen.get(&expired_key).map(|entry| (entry.expiry.as_ref() == Some(&expiry), entry.generation));
+                    if let Some((true, generation)) = current {
+                        seen.remove(&expired_key);
+                        if let Some(filters) = filters.as_mut() {
+                            filters.release(generation);
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// A Bloom filter over hashes of keys.
+struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    fn new(keys: usize, false_positive_rate: f64) -> Self {
+        let ln2 = ::std::f64::consts::LN_2;
+        let bits = (-(keys as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((bits as f64 / keys as f64) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; (bits + 63) / 64],
+            hashes,
+        }
+    }
+
+    // The bit positions of a key, by double hashing.
+    fn positions<K: Hash>(&self, key: &K) -> impl Iterator<Item=usize> {
+        let mut hasher1 = DefaultHasher::new();
+        key.hash(&mut hasher1);
+        let hash1 = hasher1.finish();
+        let mut hasher2 = DefaultHasher::new();
+        (hash1, key).hash(&mut hasher2);
+        let hash2 = hasher2.finish() | 1;
+        let len = (self.bits.len() * 64) as u64;
+        (0 .. self.hashes as u64).map(move |index| (hash1.wrapping_add(index.wrapping_mul(hash2)) % len) as usize)
+    }
+
+    fn insert<K: Hash>(&mut self, key: &K) {
+        for position in self.positions(key).collect::<Vec<_>>() {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    fn contains<K: Hash>(&self, key: &K) -> bool {
+        self.positions(key).all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+}
+
+/// Bloom filters of remembered keys, in generations of a bounded number of keys.
+struct BloomGenerations {
+    keys_per_filter: usize,
+    false_positive_rate: f64,
+    // (generation, filter, keys inserted, keys still remembered), oldest first
+    generations: Vec<(u64, BloomFilter, usize, usize)>,
+    next_generation: u64,
+}
+
+impl BloomGenerations {
+    fn new(keys_per_filter: usize, false_positive_rate: f64) -> Self {
+        BloomGenerations {
+            keys_per_filter,
+            false_positive_rate,
+            generations: Vec::new(),
+            next_generation: 0,
+        }
+    }
+
+    fn contains<K: Hash>(&self, key: &K) -> bool {
+        self.generations.iter().any(|(_, filter, _, _)| filter.contains(key))
+    }
+
+    /// Adds a key to the current generation, starting a new one if it is full, and returns its generation.
+    fn insert<K: Hash>(&mut self, key: &K) -> u64 {
+        if self.generations.last().map(|(_, _, inserted, _)| *inserted >= self.keys_per_filter).unwrap_or(true) {
+            self.generations.push((self.next_generation, BloomFilter::new(self.keys_per_filter, self.false_positive_rate), 0, 0));
+            self.next_generation += 1;
+        }
+        let current = self.generations.last_mut().unwrap();
+        current.1.insert(key);
+        current.2 += 1;
+        current.3 += 1;
+        current.0
+    }
+
+    /// Records that a key of `generation` is no longer remembered, discarding generations without keys.
+    fn release(&mut self, generation: u64) {
+        if let Some(entry) = self.generations.iter_mut().find(|(other, _, _, _)| *other == generation) {
+            entry.3 -= 1;
+        }
+        let current = self.generations.last().map(|(generation, _, _, _)| *generation);
+        self.generations.retain(|(generation, _, _, remembered)| *remembered > 0 || Some(*generation) == current);
+    }
+}
@@ -0,0 +1,206 @@
+//! Enrichment of a stream with the contents of a slowly changing table.
+//!
+//! The table is given by a stream of `(key, Option<value>)` updates, where `None` removes the key.
+//! Updates are applied to a multi-version managed map at their times, and each record of the enriched
+//! stream is looked up as of its own time. Records are held until the frontier of the updates has
+//! passed their time, so that the table they are looked up against is complete.
+
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::ExchangeData;
+use crate::progress::frontier::{Antichain, AntichainRef};
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::{Exchange, ParallelizationContract, Pipeline};
+use crate::dataflow::operators::{Broadcast, Capability};
+use crate::dataflow::operators::generic::operator::Operator;
+use crate::state::multiversion::MultiVersionManagedMap;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+
+/// The value of a table key, or its absence.
+#[derive(Clone, Serialize, Deserialize)]
+struct TableValue<U> {
+    value: Option<U>,
+}
+
+impl<U> FasterRmw for TableValue<U> where TableValue<U>: FasterValue {
+    fn rmw(&self, modification: Self) -> Self {
+        modification
+    }
+}
+
+/// Extension trait for `Stream`.
+pub trait Enrich<G: Scope, D: ExchangeData+FasterValue> where G::Timestamp: FasterKey {
+    /// Pairs each record with the value of its key, as extracted by `key`, in the table of `updates`
+    /// as of the record's time. The updates are broadcast to all workers.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Delay, Map, Enrich, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let updates = vec![(0u64, (1u64, Some("one".to_string()))), (2, (1, Some("uno".to_string()))), (4, (1, None))]
+    ///         .to_stream(scope)
+    ///         .delay(|x, _| x.0)
+    ///         .map(|(_, update)| update);
+    ///     vec![1u64, 2, 3, 4, 5]
+    ///         .to_stream(scope)
+    ///         .delay(|x, _| *x)
+    ///         .enrich(&updates, |_| 1u64)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(1, vec![(1, Some("one".to_string()))]),
+    ///                                     (2, vec![(2, Some("uno".to_string()))]),
+    ///                                     (3, vec![(3, Some("uno".to_string()))]),
+    ///                                     (4, vec![(4, None)]),
+    ///                                     (5, vec![(5, None)])]);
+    /// ```
+    fn enrich<K, U, E>(&self, updates: &Stream<G, (K, Option<U>)>, key: E) -> Stream<G, (D, Option<U>)>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        U: ExchangeData+FasterValue,
+        E: Fn(&D)->K+'static;
+
+    /// Pairs each record with the value of its key as `enrich` does, with the table partitioned
+    /// among workers by `hash` rather than broadcast.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Enrich, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let updates = vec![(0u64, Some("zero".to_string())), (1, Some("one".to_string()))].to_stream(scope);
+    ///     vec![1u64, 2].to_stream(scope)
+    ///         .enrich_partitioned(&updates, |x| *x, |key| *key)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, vec![(1, Some("one".to_string())), (2, None)])]);
+    /// ```
+    fn enrich_partitioned<K, U, E, H>(&self, updates: &Stream<G, (K, Option<U>)>, key: E, hash: H) -> Stream<G, (D, Option<U>)>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        U: ExchangeData+FasterValue,
+        E: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static;
+}
+
+impl<G: Scope, D: ExchangeData+FasterValue> Enrich<G, D> for Stream<G, D> where G::Timestamp: FasterKey {
+
+    fn enrich<K, U, E>(&self, updates: &Stream<G, (K, Option<U>)>, key: E) -> Stream<G, (D, Option<U>)>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        U: ExchangeData+FasterValue,
+        E: Fn(&D)->K+'static,
+    {
+        enrich_core(&updates.broadcast(), self, Pipeline, Pipeline, key, "Enrich")
+    }
+
+    fn enrich_partitioned<K, U, E, H>(&self, updates: &Stream<G, (K, Option<U>)>, key: E, hash: H) -> Stream<G, (D, Option<U>)>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        U: ExchangeData+FasterValue,
+        E: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        let key = Rc::new(key);
+        let hash1 = Rc::new(hash);
+        let hash2 = hash1.clone();
+        let key2 = key.clone();
+        let exchange1 = Exchange::new(move |&(ref k, _): &(K, Option<U>)| hash1(k));
+        let exchange2 = Exchange::new(move |datum: &D| hash2(&key2(datum)));
+        enrich_core(updates, self, exchange1, exchange2, move |datum: &D| key(datum), "EnrichPartitioned")
+    }
+}
+
+fn enrich_core<G, D, K, U, E, P1, P2>(
+    updates: &Stream<G, (K, Option<U>)>,
+    stream: &Stream<G, D>,
+    pact1: P1,
+    pact2: P2,
+    key: E,
+    name: &str) -> Stream<G, (D, Option<U>)>
+where
+    G: Scope,
+    G::Timestamp: FasterKey,
+    D: ExchangeData+FasterValue,
+    K: ExchangeData+Hash+Eq+FasterKey,
+    U: ExchangeData+FasterValue,
+    E: Fn(&D)->K+'static,
+    P1: ParallelizationContract<G::Timestamp, (K, Option<U>)>,
+    P2: ParallelizationContract<G::Timestamp, D>,
+{
+    updates.binary_frontier(stream, pact1, pact2, name, move |_capability, _info, state_handle| {
+
+        // key -> versions of its value
+        let mut table = MultiVersionManagedMap::<K, G::Timestamp, TableValue<U>, _>::new(&state_handle, "table");
+        // time -> records awaiting the completion of the table at that time
+        let mut pending = state_handle.get_managed_map::<G::Timestamp, Vec<D>>("pending");
+        let mut capabilities: Vec<Capability<G::Timestamp>> = Vec::new();
+
+        let mut compaction = Antichain::from_elem(Default::default());
+        let mut updates_vector = Vec::new();
+        let mut vector = Vec::new();
+
+        move |input1, input2, output| {
+
+            while let Some((time, data)) = input1.next() {
+                data.swap(&mut updates_vector);
+                for (update_key, value) in updates_vector.drain(..) {
+                    table.insert(update_key, time.time().clone(), TableValue { value });
+                }
+            }
+
+            // records whose table is complete are looked up at once, and the others held.
+            while let Some((time, data)) = input2.next() {
+                data.swap(&mut vector);
+                if input1.frontier().less_equal(time.time()) {
+                    pending.rmw(time.time().clone(), vector.drain(..).collect());
+                    if capabilities.iter().all(|capability| capability.time() != time.time()) {
+                        capabilities.push(time.retain());
+                    }
+                }
+                else {
+                    let mut session = output.session(&time);
+                    for datum in vector.drain(..) {
+                        let value = table.get_at(&key(&datum), time.time()).and_then(|entry| entry.value);
+                        session.give((datum, value));
+                    }
+                }
+            }
+
+            // release held records once the frontier of the updates has passed their time.
+            let mut index = 0;
+            while index < capabilities.len() {
+                if !input1.frontier().less_equal(capabilities[index].time()) {
+                    let capability = capabilities.swap_remove(index);
+                    let mut session = output.session(&capability);
+                    for datum in pending.remove(capability.time()).unwrap_or_default() {
+                        let value = table.get_at(&key(&datum), capability.time()).and_then(|entry| entry.value);
+                        session.give((datum, value));
+                    }
+                }
+                else {
+                    index += 1;
+                }
+            }
+
+            // versions are needed for updates and lookups at times from the frontiers and held records on.
+            let mut frontier = Antichain::new();
+            for time in input1.frontier().frontier().iter().chain(input2.frontier().frontier().iter()) {
+                frontier.insert(time.clone());
+            }
+            for capability in capabilities.iter() {
+                frontier.insert(capability.time().clone());
+            }
+            if frontier != compaction {
+                table.advance_to(AntichainRef::new(frontier.elements()));
+                compaction = frontier;
+            }
+        }
+    })
+}
@@ -15,5 +15,5 @@ pub use self::notificator::{Notificator, FrontierNotificator};
 
 // pub use self::unary::Unary;
 // pub use self::binary::Binary;
-pub use self::operator::{Operator, source};
+pub use self::operator::{Operator, source, nary_frontier, nary_frontier_core};
 pub use self::operator_info::OperatorInfo;
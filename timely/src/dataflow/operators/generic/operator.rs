@@ -15,9 +15,12 @@ use super::builder_rc::OperatorBuilder;
 use crate::dataflow::operators::generic::OperatorInfo;
 use crate::dataflow::operators::generic::notificator::{Notificator, FrontierNotificator};
 use crate::state::{StateBackend, StateHandle};
-use std::rc::Rc;
 
 /// Methods to construct generic streaming and blocking operators.
+///
+/// The n-ary constructors take inputs that share the record type `D1` of this stream and one
+/// parallelization contract type, and produce outputs that share one record type. Operators over
+/// inputs or outputs of different types are built with `OperatorBuilder` directly.
 pub trait Operator<G: Scope, D1: Data> {
     /// Creates a new dataflow operator that partitions its input stream by a parallelization
     /// strategy `pact`, and repeatedly invokes `logic`, the function returned by the function passed as `constructor`.
@@ -610,6 +613,91 @@ pub trait Operator<G: Scope, D1: Data> {
         P2: ParallelizationContract<G::Timestamp, D2>,
         S: StateBackend;
 
+    /// Creates a new dataflow operator with this stream and `others` as inputs, and `outputs`
+    /// outputs, as `nary_frontier` does. This stream is the first input, partitioned by `pact`.
+    ///
+    /// All inputs have the record type `D1`, and the parallelization contract type `P`, and all
+    /// outputs have the record type `D2`.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::generic::Operator;
+    /// use timely::dataflow::channels::pact::Pipeline;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let others = vec![((3u64..6).to_stream(scope), Pipeline), ((6u64..9).to_stream(scope), Pipeline)];
+    ///     // merges the inputs, and splits their records into evens and odds.
+    ///     let mut outputs = (0u64..3).to_stream(scope).nary_frontier(Pipeline, others, 2, "EvensOdds", |_capabilities, _info, _state_handle| {
+    ///         let mut vector = Vec::new();
+    ///         move |inputs, outputs| {
+    ///             for input in inputs.iter_mut() {
+    ///                 while let Some((time, data)) = input.next() {
+    ///                     data.swap(&mut vector);
+    ///                     for datum in vector.drain(..) {
+    ///                         outputs[(datum % 2) as usize].session(&time).give(datum);
+    ///                     }
+    ///                 }
+    ///             }
+    ///         }
+    ///     });
+    ///     let odds = outputs.pop().unwrap();
+    ///     let evens = outputs.pop().unwrap();
+    ///     (evens.capture(), odds.capture())
+    /// });
+    ///
+    /// assert_eq!(captured.0.extract(), vec![(0, vec![0, 2, 4, 6, 8])]);
+    /// assert_eq!(captured.1.extract(), vec![(0, vec![1, 3, 5, 7])]);
+    /// ```
+    fn nary_frontier<D2, B, L, P>(&self, pact: P, others: Vec<(Stream<G, D1>, P)>, outputs: usize, name: &str, constructor: B) -> Vec<Stream<G, D2>>
+    where
+        D2: Data,
+        B: FnOnce(Vec<Capability<G::Timestamp>>, OperatorInfo, StateHandle<G::StateBackend>) -> L,
+        L: FnMut(&mut [FrontieredInputHandle<G::Timestamp, D1, P::Puller>],
+                 &mut [OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>])+'static,
+        P: ParallelizationContract<G::Timestamp, D1>;
+
+    /// Creates a new dataflow operator with this stream and `others` as inputs, as `nary_frontier`
+    /// does, with a state handle on a backend of type `S`.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Inspect};
+    /// use timely::dataflow::operators::generic::Operator;
+    /// use timely::dataflow::channels::pact::Pipeline;
+    /// use timely::state::StateHandle;
+    /// use timely::state::backends::InMemoryBackend;
+    ///
+    /// timely::example(|scope| {
+    ///     let others = vec![((3u64..6).to_stream(scope), Pipeline)];
+    ///     let outputs = (0u64..3).to_stream(scope).nary_frontier_core(Pipeline, others, 1, "Sum", |_capabilities, _info, state_handle: StateHandle<InMemoryBackend>| {
+    ///         let mut sum = state_handle.get_managed_value::<u64>("sum");
+    ///         let mut vector = Vec::new();
+    ///         move |inputs, outputs| {
+    ///             for input in inputs.iter_mut() {
+    ///                 while let Some((time, data)) = input.next() {
+    ///                     data.swap(&mut vector);
+    ///                     for datum in vector.drain(..) {
+    ///                         sum.rmw(datum);
+    ///                     }
+    ///                     outputs[0].session(&time).give(sum.get().map(|sum| *sum).unwrap_or(0));
+    ///                 }
+    ///             }
+    ///         }
+    ///     });
+    ///     outputs[0].inspect(|x| assert!(*x <= 15));
+    /// });
+    /// ```
+    fn nary_frontier_core<D2, B, L, P, S>(&self, pact: P, others: Vec<(Stream<G, D1>, P)>, outputs: usize, name: &str, constructor: B) -> Vec<Stream<G, D2>>
+    where
+        D2: Data,
+        B: FnOnce(Vec<Capability<G::Timestamp>>, OperatorInfo, StateHandle<S>) -> L,
+        L: FnMut(&mut [FrontieredInputHandle<G::Timestamp, D1, P::Puller>],
+                 &mut [OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>])+'static,
+        P: ParallelizationContract<G::Timestamp, D1>,
+        S: StateBackend;
+
     /// Creates a new dataflow operator that partitions its input stream by a parallelization
     /// strategy `pact`, and repeatedly invokes the function `logic` which can read from the input stream
     /// and inspect the frontier at the input.
@@ -651,7 +739,7 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
     {
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = self.scope().get_state_handle().create_sub_handle(&operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
 
         let mut input = builder.new_input(self, pact);
         let (mut output, stream) = builder.new_output();
@@ -682,7 +770,8 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
 
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = StateHandle::new(Rc::new(S::new()), &operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
+        let state_handle = state_handle.with_backend(state_handle.create_backend::<S>());
 
         let mut input = builder.new_input(self, pact);
         let (mut output, stream) = builder.new_output();
@@ -757,7 +846,7 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
     {
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = self.scope().get_state_handle().create_sub_handle(&operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
 
         let mut input = builder.new_input(self, pact);
         let (mut output, stream) = builder.new_output();
@@ -788,7 +877,8 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
 
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = StateHandle::new(Rc::new(S::new()), &operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
+        let state_handle = state_handle.with_backend(state_handle.create_backend::<S>());
 
         let mut input = builder.new_input(self, pact);
         let (mut output, stream) = builder.new_output();
@@ -820,7 +910,7 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
     {
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = self.scope().get_state_handle().create_sub_handle(&operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
 
         let mut input1 = builder.new_input(self, pact1);
         let mut input2 = builder.new_input(other, pact2);
@@ -856,7 +946,8 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
 
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = StateHandle::new(Rc::new(S::new()), &operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
+        let state_handle = state_handle.with_backend(state_handle.create_backend::<S>());
 
         let mut input1 = builder.new_input(self, pact1);
         let mut input2 = builder.new_input(other, pact2);
@@ -943,7 +1034,7 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
     {
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = self.scope().get_state_handle().create_sub_handle(&operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
 
         let mut input1 = builder.new_input(self, pact1);
         let mut input2 = builder.new_input(other, pact2);
@@ -978,7 +1069,8 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
 
         let mut builder = OperatorBuilder::new(name.to_owned(), self.scope());
         let operator_info = builder.operator_info();
-        let state_handle = StateHandle::new(Rc::new(S::new()), &operator_info.global_id.to_string());
+        let state_handle = self.scope().get_state_handle().create_operator_handle(name, &operator_info.address);
+        let state_handle = state_handle.with_backend(state_handle.create_backend::<S>());
 
         let mut input1 = builder.new_input(self, pact1);
         let mut input2 = builder.new_input(other, pact2);
@@ -998,6 +1090,33 @@ impl<G: Scope, D1: Data> Operator<G, D1> for Stream<G, D1> {
         stream
     }
 
+    fn nary_frontier<D2, B, L, P>(&self, pact: P, others: Vec<(Stream<G, D1>, P)>, outputs: usize, name: &str, constructor: B) -> Vec<Stream<G, D2>>
+    where
+        D2: Data,
+        B: FnOnce(Vec<Capability<G::Timestamp>>, OperatorInfo, StateHandle<G::StateBackend>) -> L,
+        L: FnMut(&mut [FrontieredInputHandle<G::Timestamp, D1, P::Puller>],
+                 &mut [OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>])+'static,
+        P: ParallelizationContract<G::Timestamp, D1> {
+
+        let mut inputs = vec![(self.clone(), pact)];
+        inputs.extend(others);
+        nary_frontier(&self.scope(), inputs, outputs, name, constructor)
+    }
+
+    fn nary_frontier_core<D2, B, L, P, S>(&self, pact: P, others: Vec<(Stream<G, D1>, P)>, outputs: usize, name: &str, constructor: B) -> Vec<Stream<G, D2>>
+    where
+        D2: Data,
+        B: FnOnce(Vec<Capability<G::Timestamp>>, OperatorInfo, StateHandle<S>) -> L,
+        L: FnMut(&mut [FrontieredInputHandle<G::Timestamp, D1, P::Puller>],
+                 &mut [OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>])+'static,
+        P: ParallelizationContract<G::Timestamp, D1>,
+        S: StateBackend {
+
+        let mut inputs = vec![(self.clone(), pact)];
+        inputs.extend(others);
+        nary_frontier_core(&self.scope(), inputs, outputs, name, constructor)
+    }
+
     fn sink<L, P>(&self, pact: P, name: &str, mut logic: L)
     where
         L: FnMut(&mut FrontieredInputHandle<G::Timestamp, D1, P::Puller>)+'static,
@@ -1105,3 +1224,163 @@ pub fn empty<G: Scope, D: Data>(scope: &G) -> Stream<G, D> {
         // drop capability, do nothing
     })
 }
+
+/// Creates a new dataflow operator with any number of inputs and outputs, which partitions each
+/// input stream by its parallelization strategy and repeatedly invokes `logic`, the function
+/// returned by the function passed as `constructor`.
+///
+/// The constructor receives a capability for each output, and `logic` receives a handle to each
+/// input, through which it can read data and inspect the input frontier, and a handle to each
+/// output, in the order of `inputs` and `outputs`. Each input is connected to every output.
+///
+/// All inputs have the record type `D1` and the parallelization contract type `P`, and all outputs
+/// the record type `D2`. Operators over inputs or outputs of different types are built with
+/// `OperatorBuilder` directly.
+///
+/// # Examples
+/// ```
+/// use timely::dataflow::operators::{ToStream, Capture};
+/// use timely::dataflow::operators::capture::Extract;
+/// use timely::dataflow::operators::generic::operator::nary_frontier;
+/// use timely::dataflow::channels::pact::Pipeline;
+///
+/// let captured = timely::example(|scope| {
+///     let inputs = vec![
+///         ((0u64..3).to_stream(scope), Pipeline),
+///         ((3u64..6).to_stream(scope), Pipeline),
+///         ((6u64..9).to_stream(scope), Pipeline),
+///     ];
+///     // merges the inputs, and splits their records into evens and odds.
+///     let mut outputs = nary_frontier(scope, inputs, 2, "EvensOdds", |_capabilities, _info, _state_handle| {
+///         let mut vector = Vec::new();
+///         move |inputs, outputs| {
+///             for input in inputs.iter_mut() {
+///                 while let Some((time, data)) = input.next() {
+///                     data.swap(&mut vector);
+///                     for datum in vector.drain(..) {
+///                         outputs[(datum % 2) as usize].session(&time).give(datum);
+///                     }
+///                 }
+///             }
+///         }
+///     });
+///     let odds = outputs.pop().unwrap();
+///     let evens = outputs.pop().unwrap();
+///     (evens.capture(), odds.capture())
+/// });
+///
+/// assert_eq!(captured.0.extract(), vec![(0, vec![0, 2, 4, 6, 8])]);
+/// assert_eq!(captured.1.extract(), vec![(0, vec![1, 3, 5, 7])]);
+/// ```
+pub fn nary_frontier<G, D1, D2, P, B, L>(scope: &G, inputs: Vec<(Stream<G, D1>, P)>, outputs: usize, name: &str, constructor: B) -> Vec<Stream<G, D2>>
+where
+    G: Scope,
+    D1: Data,
+    D2: Data,
+    P: ParallelizationContract<G::Timestamp, D1>,
+    B: FnOnce(Vec<Capability<G::Timestamp>>, OperatorInfo, StateHandle<G::StateBackend>) -> L,
+    L: FnMut(&mut [FrontieredInputHandle<G::Timestamp, D1, P::Puller>],
+             &mut [OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>])+'static {
+
+    nary_frontier_with_handle(scope, inputs, outputs, name, constructor, |info| {
+        scope.get_state_handle().create_operator_handle(name, &info.address)
+    })
+}
+
+/// Creates a new dataflow operator with any number of inputs and outputs, as `nary_frontier` does.
+///
+/// This variant allows specifying the state backend that will be used by `state_handle`.
+///
+/// # Examples
+/// ```
+/// use timely::dataflow::operators::{ToStream, Inspect};
+/// use timely::dataflow::operators::generic::operator::nary_frontier_core;
+/// use timely::dataflow::channels::pact::Pipeline;
+/// use timely::state::StateHandle;
+/// use timely::state::backends::InMemoryBackend;
+///
+/// timely::example(|scope| {
+///     let inputs = vec![((0u64..3).to_stream(scope), Pipeline), ((3u64..6).to_stream(scope), Pipeline)];
+///     let outputs = nary_frontier_core(scope, inputs, 1, "Sum", |_capabilities, _info, state_handle: StateHandle<InMemoryBackend>| {
+///         let mut sum = state_handle.get_managed_value::<u64>("sum");
+///         let mut vector = Vec::new();
+///         move |inputs, outputs| {
+///             for input in inputs.iter_mut() {
+///                 while let Some((time, data)) = input.next() {
+///                     data.swap(&mut vector);
+///                     for datum in vector.drain(..) {
+///                         sum.rmw(datum);
+///                     }
+///                     outputs[0].session(&time).give(sum.get().map(|sum| *sum).unwrap_or(0));
+///                 }
+///             }
+///         }
+///     });
+///     outputs[0].inspect(|x| assert!(*x <= 15));
+/// });
+/// ```
+pub fn nary_frontier_core<G, D1, D2, P, B, L, S>(scope: &G, inputs: Vec<(Stream<G, D1>, P)>, outputs: usize, name: &str, constructor: B) -> Vec<Stream<G, D2>>
+where
+    G: Scope,
+    D1: Data,
+    D2: Data,
+    P: ParallelizationContract<G::Timestamp, D1>,
+    B: FnOnce(Vec<Capability<G::Timestamp>>, OperatorInfo, StateHandle<S>) -> L,
+    L: FnMut(&mut [FrontieredInputHandle<G::Timestamp, D1, P::Puller>],
+             &mut [OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>])+'static,
+    S: StateBackend {
+
+    nary_frontier_with_handle(scope, inputs, outputs, name, constructor, |info| {
+        let state_handle = scope.get_state_handle().create_operator_handle(name, &info.address);
+        state_handle.with_backend(state_handle.create_backend::<S>())
+    })
+}
+
+fn nary_frontier_with_handle<G, D1, D2, P, B, L, S, H>(scope: &G, inputs: Vec<(Stream<G, D1>, P)>, outputs: usize, name: &str, constructor: B, state_handle: H) -> Vec<Stream<G, D2>>
+where
+    G: Scope,
+    D1: Data,
+    D2: Data,
+    P: ParallelizationContract<G::Timestamp, D1>,
+    B: FnOnce(Vec<Capability<G::Timestamp>>, OperatorInfo, StateHandle<S>) -> L,
+    L: FnMut(&mut [FrontieredInputHandle<G::Timestamp, D1, P::Puller>],
+             &mut [OutputHandle<G::Timestamp, D2, Tee<G::Timestamp, D2>>])+'static,
+    S: StateBackend,
+    H: FnOnce(&OperatorInfo) -> StateHandle<S> {
+
+    let mut builder = OperatorBuilder::new(name.to_owned(), scope.clone());
+    let operator_info = builder.operator_info();
+    let state_handle = state_handle(&operator_info);
+
+    // outputs are created first, so that each input is connected to all of them.
+    let mut output_wrappers = Vec::with_capacity(outputs);
+    let mut streams = Vec::with_capacity(outputs);
+    for _ in 0 .. outputs {
+        let (output, stream) = builder.new_output();
+        output_wrappers.push(output);
+        streams.push(stream);
+    }
+    let mut input_handles = inputs
+        .into_iter()
+        .map(|(stream, pact)| builder.new_input(&stream, pact))
+        .collect::<Vec<_>>();
+
+    builder.build(move |capabilities| {
+        // `capabilities` has one element for each output.
+        let mut logic = constructor(capabilities, operator_info, state_handle);
+        move |frontiers| {
+            let mut inputs = input_handles
+                .iter_mut()
+                .zip(frontiers.iter())
+                .map(|(input, frontier)| FrontieredInputHandle::new(input, frontier))
+                .collect::<Vec<_>>();
+            let mut outputs = output_wrappers
+                .iter_mut()
+                .map(|output| output.activate())
+                .collect::<Vec<_>>();
+            logic(&mut inputs[..], &mut outputs[..]);
+        }
+    });
+
+    streams
+}
@@ -0,0 +1,168 @@
+//! Joins pairing records of two streams whose timestamps lie within a bounded interval.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::ExchangeData;
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::Capability;
+use crate::dataflow::operators::generic::operator::Operator;
+use crate::state::primitives::ManagedMap;
+
+use faster_rs::{FasterKey, FasterValue};
+
+/// Extension trait for `Stream`.
+pub trait IntervalJoin<G: Scope<Timestamp=u64>, K: ExchangeData+Hash+Eq+FasterKey, V1: ExchangeData+FasterValue> {
+    /// Produces a `(key, val1, val2)` triple for each record of this stream at time `t1` and record of
+    /// `other` with the same key at a time `t2` in `[t1 - lower, t1 + upper]`.
+    ///
+    /// Records are buffered by time until both input frontiers have passed their time, and then
+    /// retained per key, in time order, for as long as records of the other input may still match them.
+    /// Each match is produced once the later of its two times is complete, at that time.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Delay, Map, IntervalJoin, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let clicks = vec![5u64, 10].to_stream(scope).delay(|x, _| *x).map(|x| (0u64, x));
+    ///     let views = vec![3u64, 7, 20].to_stream(scope).delay(|x, _| *x).map(|x| (0u64, x));
+    ///     clicks.interval_join(&views, 2, 3, |key| *key).capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(5, vec![(0, 5, 3)]), (7, vec![(0, 5, 7)])]);
+    /// ```
+    fn interval_join<V2, H>(&self, other: &Stream<G, (K, V2)>, lower: u64, upper: u64, hash: H) -> Stream<G, (K, V1, V2)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static;
+}
+
+impl<G: Scope<Timestamp=u64>, K: ExchangeData+Hash+Eq+FasterKey, V1: ExchangeData+FasterValue> IntervalJoin<G, K, V1> for Stream<G, (K, V1)> {
+
+    fn interval_join<V2, H>(&self, other: &Stream<G, (K, V2)>, lower: u64, upper: u64, hash: H) -> Stream<G, (K, V1, V2)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static,
+    {
+        let hash1 = Rc::new(hash);
+        let hash2 = hash1.clone();
+        let exchange1 = Exchange::new(move |&(ref k, _): &(K, V1)| hash1(k));
+        let exchange2 = Exchange::new(move |&(ref k, _): &(K, V2)| hash2(k));
+
+        self.binary_frontier(other, exchange1, exchange2, "IntervalJoin", |_capability, _info, state_handle| {
+
+            // time -> records not yet processed, for each input
+            let mut pending1 = state_handle.get_managed_map::<u64, Vec<(K, V1)>>("pending1");
+            let mut pending2 = state_handle.get_managed_map::<u64, Vec<(K, V2)>>("pending2");
+            // key -> processed records that may still match, in time order, for each input
+            let mut retained1 = state_handle.get_managed_map::<K, Vec<(u64, V1)>>("retained1");
+            let mut retained2 = state_handle.get_managed_map::<K, Vec<(u64, V2)>>("retained2");
+            // eviction time -> keys with records that can no longer match from then on, for each input
+            let mut evictions1 = state_handle.get_managed_map::<u64, Vec<K>>("evictions1");
+            let mut evictions2 = state_handle.get_managed_map::<u64, Vec<K>>("evictions2");
+            let mut eviction_times1 = BTreeSet::new();
+            let mut eviction_times2 = BTreeSet::new();
+
+            // a capability for each time with pending records
+            let mut capabilities: BTreeMap<u64, Capability<u64>> = BTreeMap::new();
+
+            let mut vector1 = Vec::new();
+            let mut vector2 = Vec::new();
+
+            move |input1, input2, output| {
+
+                while let Some((time, data)) = input1.next() {
+                    data.swap(&mut vector1);
+                    pending1.rmw(*time.time(), vector1.drain(..).collect());
+                    capabilities.entry(*time.time()).or_insert_with(|| time.retain());
+                }
+                while let Some((time, data)) = input2.next() {
+                    data.swap(&mut vector2);
+                    pending2.rmw(*time.time(), vector2.drain(..).collect());
+                    capabilities.entry(*time.time()).or_insert_with(|| time.retain());
+                }
+
+                // process complete times in order, matching each record against earlier or equal times.
+                while let Some(time) = capabilities.keys().next().cloned().filter(|time| {
+                    !input1.frontier().less_equal(time) && !input2.frontier().less_equal(time)
+                }) {
+                    let capability = capabilities.remove(&time).unwrap();
+                    let records1 = pending1.remove(&time).unwrap_or_default();
+                    let records2 = pending2.remove(&time).unwrap_or_default();
+
+                    // records of the second input become visible to those of the first at the same time.
+                    for (key, val2) in records2.iter() {
+                        retain(key, time, val2.clone(), &mut retained2);
+                        evict_at(key, time + lower + 1, &mut evictions2, &mut eviction_times2);
+                    }
+
+                    let mut session = output.session(&capability);
+                    for (key, val1) in records1 {
+                        if let Some(entries) = retained2.get(&key) {
+                            for (_, val2) in entries.iter().filter(|(time2, _)| time.saturating_sub(lower) <= *time2) {
+                                session.give((key.clone(), val1.clone(), val2.clone()));
+                            }
+                        }
+                        retain(&key, time, val1, &mut retained1);
+                        evict_at(&key, time + upper + 1, &mut evictions1, &mut eviction_times1);
+                    }
+                    for (key, val2) in records2 {
+                        if let Some(entries) = retained1.get(&key) {
+                            for (_, val1) in entries.iter().filter(|(time1, _)| time.saturating_sub(upper) <= *time1 && *time1 < time) {
+                                session.give((key.clone(), val1.clone(), val2.clone()));
+                            }
+                        }
+                    }
+
+                    // discard records that no later time can match.
+                    evict(time + 1, &mut eviction_times1, &mut evictions1, &mut retained1, |time1| time1 + upper + 1);
+                    evict(time + 1, &mut eviction_times2, &mut evictions2, &mut retained2, |time2| time2 + lower + 1);
+                }
+            }
+        })
+    }
+}
+
+fn retain<K, V>(key: &K, time: u64, value: V, retained: &mut Box<ManagedMap<K, Vec<(u64, V)>>>)
+where
+    K: ExchangeData+Hash+Eq+FasterKey,
+    V: ExchangeData+FasterValue,
+{
+    let mut entries = retained.remove(key).unwrap_or_default();
+    entries.push((time, value));
+    retained.insert(key.clone(), entries);
+}
+
+fn evict_at<K>(key: &K, time: u64, evictions: &mut Box<ManagedMap<u64, Vec<K>>>, eviction_times: &mut BTreeSet<u64>)
+where
+    K: ExchangeData+Hash+Eq+FasterKey,
+{
+    eviction_times.insert(time);
+    if !evictions.get(&time).map(|keys| keys.contains(key)).unwrap_or(false) {
+        evictions.rmw(time, vec![key.clone()]);
+    }
+}
+
+// Removes the records whose eviction time, as given by `eviction`, is at most `time`.
+fn evict<K, V, F>(time: u64, eviction_times: &mut BTreeSet<u64>, evictions: &mut Box<ManagedMap<u64, Vec<K>>>, retained: &mut Box<ManagedMap<K, Vec<(u64, V)>>>, eviction: F)
+where
+    K: ExchangeData+Hash+Eq+FasterKey,
+    V: ExchangeData+FasterValue,
+    F: Fn(u64)->u64,
+{
+    while let Some(eviction_time) = eviction_times.iter().next().cloned().filter(|eviction_time| *eviction_time <= time) {
+        eviction_times.remove(&eviction_time);
+        for key in evictions.remove(&eviction_time).unwrap_or_default() {
+            if let Some(mut entries) = retained.remove(&key) {
+                entries.retain(|(entry_time, _)| eviction(*entry_time) > time);
+                if !entries.is_empty() {
+                    retained.insert(key, entries);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Iteration of a computation to a fixed point.
+//!
+//! The `iterate` helper applies a step to a collection of records repeatedly, in rounds of an
+//! iterative scope. After each round, the records of the round's input and output are compared on
+//! the workers their hashes route them to, and the numbers of differing records are broadcast and
+//! summed. The output of a round without differences, or of the last permitted round, leaves the
+//! loop; the output of any other round becomes the input of the next.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::ExchangeData;
+use crate::order::Product;
+use crate::progress::Timestamp;
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::scopes::Child;
+use crate::dataflow::channels::pact::{Exchange, Pipeline};
+use crate::dataflow::operators::{Branch, Broadcast, Concat, ConnectLoop, Enter, Feedback, FrontierNotificator, Leave, Map};
+use crate::dataflow::operators::generic::operator::Operator;
+
+/// Extension trait for `Scope`.
+pub trait Iterate<G: Scope> {
+    /// Applies `step` to the records of `initial` until a round produces the same records it was
+    /// given, or for at most `max_rounds` rounds, and returns the records of the final round.
+    ///
+    /// The step is applied in an iterative scope whose inner timestamp counts rounds, and should
+    /// produce each round's records at the round's timestamp.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Iterate, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let (converged, bounded) = timely::example(|scope| {
+    ///     let initial = vec![8u64, 5].to_stream(scope);
+    ///     let converged = scope.iterate(&initial, None, |state| state.map(|x| x / 2));
+    ///     let bounded = scope.iterate(&initial, Some(2), |state| state.map(|x| x / 2));
+    ///     (converged.capture(), bounded.capture())
+    /// });
+    ///
+    /// assert_eq!(converged.extract(), vec![(0, vec![0, 0])]);
+    /// assert_eq!(bounded.extract(), vec![(0, vec![1, 2])]);
+    /// ```
+    fn iterate<D, F>(&mut self, initial: &Stream<G, D>, max_rounds: Option<u64>, step: F) -> Stream<G, D>
+    where
+        D: ExchangeData+Hash+Eq,
+        F: for<'a> FnOnce(&Stream<Child<'a, G, Product<G::Timestamp, u64>, G::StateBackend>, D>) -> Stream<Child<'a, G, Product<G::Timestamp, u64>, G::StateBackend>, D>;
+}
+
+impl<G: Scope> Iterate<G> for G {
+    fn iterate<D, F>(&mut self, initial: &Stream<G, D>, max_rounds: Option<u64>, step: F) -> Stream<G, D>
+    where
+        D: ExchangeData+Hash+Eq,
+        F: for<'a> FnOnce(&Stream<Child<'a, G, Product<G::Timestamp, u64>, G::StateBackend>, D>) -> Stream<Child<'a, G, Product<G::Timestamp, u64>, G::StateBackend>, D>,
+    {
+        assert!(max_rounds != Some(0), "Iteration must permit at least one round");
+
+        self.iterative::<u64, _, _>(|inner| {
+
+            let (handle, feedback) = inner.feedback(Product::new(Default::default(), 1));
+            let state = initial.enter(inner).concat(&feedback);
+            let next = step(&state);
+
+            let changes = count_changes(&state, &next).broadcast();
+            let (repeat, done) = gate(&next, &changes, max_rounds).branch(|_time, &(done, _)| done);
+
+            repeat.map(|(_, datum)| datum).connect_loop(handle);
+            done.map(|(_, datum)| datum).leave()
+        })
+    }
+}
+
+fn hash_of<D: Hash>(datum: &D) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    datum.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Counts, for each round, the records in which its input and output differ on this worker.
+fn count_changes<G: Scope, D: ExchangeData+Hash+Eq>(state: &Stream<G, D>, next: &Stream<G, D>) -> Stream<G, u64> {
+
+    state.binary_frontier(next, Exchange::new(hash_of), Exchange::new(hash_of), "CountChanges", |_capability, _info, _state_handle| {
+
+        // round -> record -> count in the output less count in the input
+        let mut differences: HashMap<G::Timestamp, HashMap<D, i64>> = HashMap::new();
+        let mut notificator = FrontierNotificator::new();
+        let mut vector = Vec::new();
+
+        move |input1, input2, output| {
+
+            while let Some((time, data)) = input1.next() {
+                data.swap(&mut vector);
+                let round = differences.entry(time.time().clone()).or_insert_with(HashMap::new);
+                for datum in vector.drain(..) {
+                    *round.entry(datum).or_insert(0) -= 1;
+                }
+                notificator.notify_at(time.retain());
+            }
+            while let Some((time, data)) = input2.next() {
+                data.swap(&mut vector);
+                let round = differences.entry(time.time().clone()).or_insert_with(HashMap::new);
+                for datum in vector.drain(..) {
+                    *round.entry(datum).or_insert(0) += 1;
+                }
+                notificator.notify_at(time.retain());
+            }
+
+            notificator.for_each(&[input1.frontier(), input2.frontier()], |time, _| {
+                if let Some(round) = differences.remove(time.time()) {
+                    let changes = round.values().map(|difference| difference.abs() as u64).sum::<u64>();
+                    if changes > 0 {
+                        output.session(&time).give(changes);
+                    }
+                }
+            });
+        }
+    })
+}
+
+/// Tags the output records of each round with whether they leave the loop, once the round's
+/// changes have been counted on all workers.
+fn gate<G, TOuter, D>(next: &Stream<G, D>, changes: &Stream<G, u64>, max_rounds: Option<u64>) -> Stream<G, (bool, D)>
+where
+    G: Scope<Timestamp=Product<TOuter, u64>>,
+    TOuter: Timestamp,
+    D: ExchangeData,
+{
+    next.binary_frontier(changes, Pipeline, Pipeline, "IterateGate", move |_capability, _info, _state_handle| {
+
+        // round -> records awaiting the decision of the round
+        let mut pending: HashMap<G::Timestamp, Vec<D>> = HashMap::new();
+        // round -> changes counted so far in the round
+        let mut counts: HashMap<G::Timestamp, u64> = HashMap::new();
+        let mut notificator = FrontierNotificator::new();
+        let mut vector = Vec::new();
+        let mut changes_vector = Vec::new();
+
+        move |input1, input2, output| {
+
+            while let Some((time, data)) = input1.next() {
+                data.swap(&mut vector);
+                pending.entry(time.time().clone()).or_insert_with(Vec::new).extend(vector.drain(..));
+                notificator.notify_at(time.retain());
+            }
+            while let Some((time, data)) = input2.next() {
+                data.swap(&mut changes_vector);
+                *counts.entry(time.time().clone()).or_insert(0) += changes_vector.drain(..).sum::<u64>();
+            }
+
+            notificator.for_each(&[input1.frontier(), input2.frontier()], |time, _| {
+                let changed = counts.remove(time.time()).unwrap_or(0) > 0;
+                let exhausted = max_rounds.map_or(false, |max_rounds| time.time().inner + 1 >= max_rounds);
+                let done = !changed || exhausted;
+                let mut session = output.session(&time);
+                for datum in pending.remove(time.time()).unwrap_or_default() {
+                    session.give((done, datum));
+                }
+            });
+
+            // counts of rounds without records are never consulted.
+            let frontier = input1.frontier();
+            counts.retain(|time, _| frontier.less_equal(time) || pending.contains_key(time));
+        }
+    })
+}
@@ -0,0 +1,286 @@
+//! Equi-joins of keyed streams, with each side held in managed state for a retention period.
+//!
+//! Both inputs are exchanged by key, and each record is kept in managed state until the frontiers
+//! of both inputs have passed its time advanced by the retention summary. Records meeting a record of
+//! the other input with the same key while both are retained produce a match, at the time of the later
+//! of the two to arrive. Outer joins additionally produce the records that expire without a match, at
+//! their expiry time.
+
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::ExchangeData;
+use crate::progress::Timestamp;
+use crate::progress::timestamp::PathSummary;
+use crate::progress::frontier::MutableAntichain;
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::{Capability, Map};
+use crate::dataflow::operators::generic::operator::Operator;
+use crate::state::primitives::ManagedMap;
+
+use faster_rs::{FasterKey, FasterValue};
+
+/// A retained record of one input.
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry<V, T> {
+    value: V,
+    time: T,
+    matched: bool,
+}
+
+/// Extension trait for joining `Stream`s of `(key, val)` pairs.
+pub trait Join<G: Scope, K: ExchangeData+Hash+Eq+FasterKey, V1: ExchangeData+FasterValue> where G::Timestamp: FasterKey {
+    /// Produces a `(key, val1, val2)` triple for each pair of records with the same key that meet
+    /// while retained.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Join, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let names = vec![(0u64, "zero".to_string()), (1, "one".to_string())].to_stream(scope);
+    ///     let counts = vec![(0u64, 10u64), (2, 20)].to_stream(scope);
+    ///     names.join(&counts, 0, |key| *key).capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, vec![(0, "zero".to_string(), 10)])]);
+    /// ```
+    fn join<V2, H>(&self, other: &Stream<G, (K, V2)>, retention: <G::Timestamp as Timestamp>::Summary, hash: H) -> Stream<G, (K, V1, V2)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static;
+
+    /// Produces the matches of `join`, and each record of this stream expiring without a match.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Join, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let names = vec![(0u64, "zero".to_string()), (1, "one".to_string())].to_stream(scope);
+    ///     let counts = vec![(0u64, 10u64), (2, 20)].to_stream(scope);
+    ///     names.left_join(&counts, 0, |key| *key).capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, vec![(0, "zero".to_string(), Some(10)), (1, "one".to_string(), None)])]);
+    /// ```
+    fn left_join<V2, H>(&self, other: &Stream<G, (K, V2)>, retention: <G::Timestamp as Timestamp>::Summary, hash: H) -> Stream<G, (K, V1, Option<V2>)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static;
+
+    /// Produces the matches of `join`, and each record of either stream expiring without a match.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Join, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let names = vec![(0u64, "zero".to_string()), (1, "one".to_string())].to_stream(scope);
+    ///     let counts = vec![(0u64, 10u64), (2, 20)].to_stream(scope);
+    ///     names.outer_join(&counts, 0, |key| *key).capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, vec![(0, Some("zero".to_string()), Some(10)),
+    ///                                              (1, Some("one".to_string()), None),
+    ///                                              (2, None, Some(20))])]);
+    /// ```
+    fn outer_join<V2, H>(&self, other: &Stream<G, (K, V2)>, retention: <G::Timestamp as Timestamp>::Summary, hash: H) -> Stream<G, (K, Option<V1>, Option<V2>)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static;
+}
+
+impl<G: Scope, K: ExchangeData+Hash+Eq+FasterKey, V1: ExchangeData+FasterValue> Join<G, K, V1> for Stream<G, (K, V1)> where G::Timestamp: FasterKey {
+
+    fn join<V2, H>(&self, other: &Stream<G, (K, V2)>, retention: <G::Timestamp as Timestamp>::Summary, hash: H) -> Stream<G, (K, V1, V2)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static,
+    {
+        join_core(self, other, retention, hash, false, false, "Join")
+            .map(|(key, val1, val2)| (key, val1.unwrap(), val2.unwrap()))
+    }
+
+    fn left_join<V2, H>(&self, other: &Stream<G, (K, V2)>, retention: <G::Timestamp as Timestamp>::Summary, hash: H) -> Stream<G, (K, V1, Option<V2>)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static,
+    {
+        join_core(self, other, retention, hash, true, false, "LeftJoin")
+            .map(|(key, val1, val2)| (key, val1.unwrap(), val2))
+    }
+
+    fn outer_join<V2, H>(&self, other: &Stream<G, (K, V2)>, retention: <G::Timestamp as Timestamp>::Summary, hash: H) -> Stream<G, (K, Option<V1>, Option<V2>)>
+    where
+        V2: ExchangeData+FasterValue,
+        H: Fn(&K)->u64+'static,
+    {
+        join_core(self, other, retention, hash, true, true, "OuterJoin")
+    }
+}
+
+fn join_core<G, K, V1, V2, H>(
+    stream1: &Stream<G, (K, V1)>,
+    stream2: &Stream<G, (K, V2)>,
+    retention: <G::Timestamp as Timestamp>::Summary,
+    hash: H,
+    outer1: bool,
+    outer2: bool,
+    name: &str) -> Stream<G, (K, Option<V1>, Option<V2>)>
+where
+    G: Scope,
+    G::Timestamp: FasterKey,
+    K: ExchangeData+Hash+Eq+FasterKey,
+    V1: ExchangeData+FasterValue,
+    V2: ExchangeData+FasterValue,
+    H: Fn(&K)->u64+'static,
+{
+    let hash1 = Rc::new(hash);
+    let hash2 = hash1.clone();
+    let exchange1 = Exchange::new(move |&(ref k, _): &(K, V1)| hash1(k));
+    let exchange2 = Exchange::new(move |&(ref k, _): &(K, V2)| hash2(k));
+
+    stream1.binary_frontier(stream2, exchange1, exchange2, name, |_capability, _info, state_handle| {
+
+        // key -> retained records, and expiry time -> keys with records expiring then, for each input
+        let mut state1 = state_handle.get_managed_map::<K, Vec<Entry<V1, G::Timestamp>>>("state1");
+        let mut state2 = state_handle.get_managed_map::<K, Vec<Entry<V2, G::Timestamp>>>("state2");
+        let mut expiring1 = state_handle.get_managed_map::<G::Timestamp, Vec<K>>("expiring1");
+        let mut expiring2 = state_handle.get_managed_map::<G::Timestamp, Vec<K>>("expiring2");
+
+        // a capability for each expiry time
+        let mut capabilities: Vec<Capability<G::Timestamp>> = Vec::new();
+
+        let mut vector1 = Vec::new();
+        let mut vector2 = Vec::new();
+
+        move |input1, input2, output| {
+
+            // Drain first input, probe and mark the second state, retain in the first.
+            while let Some((time, data)) = input1.next() {
+                data.swap(&mut vector1);
+                let mut session = output.session(&time);
+                for (key, val1) in vector1.drain(..) {
+                    let matches = probe(&key, &mut state2);
+                    for val2 in matches.iter() {
+                        session.give((key.clone(), Some(val1.clone()), Some(val2.clone())));
+                    }
+                    if let Some(expiry) = retention.results_in(time.time()) {
+                        expire_at(&key, expiry, &mut expiring1, &mut capabilities, &time.delayed(time.time()));
+                    }
+                    retain(&key, val1, time.time(), !matches.is_empty(), &mut state1);
+                }
+            }
+
+            // Drain second input, probe and mark the first state, retain in the second.
+            while let Some((time, data)) = input2.next() {
+                data.swap(&mut vector2);
+                let mut session = output.session(&time);
+                for (key, val2) in vector2.drain(..) {
+                    let matches = probe(&key, &mut state1);
+                    for val1 in matches.iter() {
+                        session.give((key.clone(), Some(val1.clone()), Some(val2.clone())));
+                    }
+                    if let Some(expiry) = retention.results_in(time.time()) {
+                        expire_at(&key, expiry, &mut expiring2, &mut capabilities, &time.delayed(time.time()));
+                    }
+                    retain(&key, val2, time.time(), !matches.is_empty(), &mut state2);
+                }
+            }
+
+            // Discard records once both frontiers have passed their expiry, producing unmatched ones if outer.
+            while let Some(position) = next_expired(&capabilities, input1.frontier(), input2.frontier()) {
+                let capability = capabilities.remove(position);
+                let expiry = capability.time().clone();
+                let mut session = output.session(&capability);
+                for (key, val1) in evict(&expiry, &retention, &mut expiring1, &mut state1) {
+                    if outer1 { session.give((key, Some(val1), None)); }
+                }
+                for (key, val2) in evict(&expiry, &retention, &mut expiring2, &mut state2) {
+                    if outer2 { session.give((key, None, Some(val2))); }
+                }
+            }
+        }
+    })
+}
+
+// Marks the retained records of `key` as matched, and returns their values.
+fn probe<K, V, T>(key: &K, state: &mut Box<ManagedMap<K, Vec<Entry<V, T>>>>) -> Vec<V>
+where
+    K: FasterKey+Hash+Eq+Clone,
+    V: ExchangeData+FasterValue,
+    T: Timestamp+FasterKey,
+{
+    match state.remove(key) {
+        None => Vec::new(),
+        Some(mut entries) => {
+            let values = entries.iter().map(|entry| entry.value.clone()).collect();
+            for entry in entries.iter_mut() {
+                entry.matched = true;
+            }
+            state.insert(key.clone(), entries);
+            values
+        }
+    }
+}
+
+fn retain<K, V, T>(key: &K, value: V, time: &T, matched: bool, state: &mut Box<ManagedMap<K, Vec<Entry<V, T>>>>)
+where
+    K: FasterKey+Hash+Eq+Clone,
+    V: ExchangeData+FasterValue,
+    T: Timestamp+FasterKey,
+{
+    let mut entries = state.remove(key).unwrap_or_default();
+    entries.push(Entry { value, time: time.clone(), matched });
+    state.insert(key.clone(), entries);
+}
+
+fn expire_at<K, T>(key: &K, expiry: T, expiring: &mut Box<ManagedMap<T, Vec<K>>>, capabilities: &mut Vec<Capability<T>>, capability: &Capability<T>)
+where
+    K: FasterKey+Hash+Eq+Clone+'static,
+    T: Timestamp+FasterKey,
+{
+    if !expiring.get(&expiry).map(|keys| keys.contains(key)).unwrap_or(false) {
+        expiring.rmw(expiry.clone(), vec![key.clone()]);
+    }
+    if capabilities.iter().all(|other| other.time() != &expiry) {
+        capabilities.push(capability.delayed(&expiry));
+    }
+}
+
+// Removes the records expiring at `expiry`, returning those never matched.
+fn evict<K, V, T>(expiry: &T, retention: &T::Summary, expiring: &mut Box<ManagedMap<T, Vec<K>>>, state: &mut Box<ManagedMap<K, Vec<Entry<V, T>>>>) -> Vec<(K, V)>
+where
+    K: FasterKey+Hash+Eq+Clone+'static,
+    V: ExchangeData+FasterValue,
+    T: Timestamp+FasterKey,
+{
+    let mut unmatched = Vec::new();
+    for key in expiring.remove(expiry).unwrap_or_default() {
+        if let Some(entries) = state.remove(&key) {
+            let (expired, retained): (Vec<_>, Vec<_>) = entries
+                .into_iter()
+                .partition(|entry| retention.results_in(&entry.time).as_ref() == Some(expiry));
+            unmatched.extend(expired.into_iter().filter(|entry| !entry.matched).map(|entry| (key.clone(), entry.value)));
+            if !retained.is_empty() {
+                state.insert(key, retained);
+            }
+        }
+    }
+    unmatched
+}
+
+// The earliest expiry time both frontiers have passed, if any.
+fn next_expired<T: Timestamp>(capabilities: &[Capability<T>], frontier1: &MutableAntichain<T>, frontier2: &MutableAntichain<T>) -> Option<usize> {
+    capabilities
+        .iter()
+        .enumerate()
+        .filter(|(_, capability)| !frontier1.less_equal(capability.time()) && !frontier2.less_equal(capability.time()))
+        .min_by(|(_, x), (_, y)| x.time().cmp(y.time()))
+        .map(|(position, _)| position)
+}
@@ -0,0 +1,371 @@
+//! Keyed streams, partitioned by key once and processed with per-key state and timers.
+//!
+//! A `KeyedStream` wraps a stream of `(key, val)` pairs together with the function routing keys to
+//! workers. Its operators exchange records by that function, and hand user logic a `KeyContext`
+//! through which the current key's state can be read and written, and timers registered.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::{Data, ExchangeData};
+use crate::order::PartialOrder;
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::{Capability, Map};
+use crate::dataflow::operators::generic::operator::Operator;
+use crate::state::primitives::ManagedMap;
+use crate::state::{StateBackend, StateHandle};
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+
+/// A stream of `(key, val)` pairs, together with the function routing keys to workers.
+pub struct KeyedStream<G: Scope, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData> {
+    stream: Stream<G, (K, V)>,
+    hash: Rc<Fn(&K)->u64>,
+}
+
+impl<G: Scope, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData> Clone for KeyedStream<G, K, V> {
+    fn clone(&self) -> Self {
+        KeyedStream {
+            stream: self.stream.clone(),
+            hash: self.hash.clone(),
+        }
+    }
+}
+
+/// Extension trait for `Stream`.
+pub trait KeyBy<G: Scope, D: ExchangeData> {
+    /// Keys each record by the result of `key`, routing keys to workers with `hash`.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Inspect};
+    /// use timely::dataflow::operators::keyed::KeyBy;
+    ///
+    /// timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .key_by(|x| x % 2, |key| *key)
+    ///         .stream()
+    ///         .inspect(|&(key, x)| assert_eq!(key, x % 2));
+    /// });
+    /// ```
+    fn key_by<K, F, H>(&self, key: F, hash: H) -> KeyedStream<G, K, D>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        F: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static;
+}
+
+impl<G: Scope, D: ExchangeData> KeyBy<G, D> for Stream<G, D> {
+    fn key_by<K, F, H>(&self, key: F, hash: H) -> KeyedStream<G, K, D>
+    where
+        K: ExchangeData+Hash+Eq+FasterKey,
+        F: Fn(&D)->K+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        KeyedStream::new(&self.map(move |x| (key(&x), x)), hash)
+    }
+}
+
+impl<G: Scope, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData> KeyedStream<G, K, V> {
+
+    /// Wraps a stream of `(key, val)` pairs, routing keys to workers with `hash`.
+    pub fn new<H: Fn(&K)->u64+'static>(stream: &Stream<G, (K, V)>, hash: H) -> Self {
+        KeyedStream {
+            stream: stream.clone(),
+            hash: Rc::new(hash),
+        }
+    }
+
+    /// The underlying stream of `(key, val)` pairs.
+    pub fn stream(&self) -> Stream<G, (K, V)> {
+        self.stream.clone()
+    }
+
+    /// Processes each record with access to the state of its key, and to timers.
+    ///
+    /// The logic `on_record` is invoked for each record, and `on_timer` for each key and time at which
+    /// a timer was registered, once the input frontier has passed that time. Both may produce output
+    /// through the `KeyContext`, at the time of the record or of the timer, respectively.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::keyed::KeyBy;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .key_by(|x| x % 2, |key| *key)
+    ///         .process("Totals",
+    ///             |_key, x, context| {
+    ///                 context.rmw("total", x);
+    ///                 context.register_timer(5);
+    ///             },
+    ///             |key, _time, context| {
+    ///                 let total = context.remove::<u64>("total").unwrap();
+    ///                 context.emit((*key, total));
+    ///             })
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(5, vec![(0, 20), (1, 25)])]);
+    /// ```
+    pub fn process<R, L, T>(&self, name: &str, mut on_record: L, mut on_timer: T) -> Stream<G, R>
+    where
+        R: Data,
+        L: FnMut(&K, V, &mut KeyContext<G, K, R>)+'static,
+        T: FnMut(&K, &G::Timestamp, &mut KeyContext<G, K, R>)+'static,
+        G::Timestamp: FasterKey,
+    {
+        let hash = self.hash.clone();
+        self.stream.unary_frontier(Exchange::new(move |&(ref k, _)| hash(k)), name, |_capability, _info, state_handle| {
+
+            let mut state = KeyedState::new(state_handle.create_sub_handle("keyed"));
+            // time -> keys with a timer at that time, and a capability for each such time
+            let mut timers = state_handle.get_managed_map::<G::Timestamp, Vec<K>>("timers");
+            let mut capabilities: Vec<Capability<G::Timestamp>> = Vec::new();
+
+            let mut registered = Vec::new();
+            let mut produced = Vec::new();
+            let mut vector = Vec::new();
+
+            move |input, output| {
+
+                while let Some((time, data)) = input.next() {
+                    data.swap(&mut vector);
+                    let mut session = output.session(&time);
+                    for (key, val) in vector.drain(..) {
+                        on_record(&key, val, &mut KeyContext {
+                            key: &key,
+                            time: time.time(),
+                            state: &mut state,
+                            timers: &mut registered,
+                            output: &mut produced,
+                        });
+                        session.give_vec(&mut produced);
+                        for timer in registered.drain(..) {
+                            assert!(time.time().less_equal(&timer), "Timer registered before the current time");
+                            if !timers.get(&timer).map(|keys| keys.contains(&key)).unwrap_or(false) {
+                                timers.rmw(timer.clone(), vec![key.clone()]);
+                            }
+                            if capabilities.iter().all(|capability| capability.time() != &timer) {
+                                capabilities.push(time.delayed(&timer));
+                            }
+                        }
+                    }
+                }
+
+                // fire timers whose time the frontier has passed, earliest first.
+                while let Some(position) = next_timer(&capabilities, input.frontier()) {
+                    let capability = capabilities.remove(position);
+                    let mut session = output.session(&capability);
+                    for key in timers.remove(capability.time()).unwrap_or_default() {
+                        on_timer(&key, capability.time(), &mut KeyContext {
+                            key: &key,
+                            time: capability.time(),
+                            state: &mut state,
+                            timers: &mut registered,
+                            output: &mut produced,
+                        });
+                        session.give_vec(&mut produced);
+                        for timer in registered.drain(..) {
+                            assert!(capability.time().less_equal(&timer), "Timer registered before the current time");
+                            if !timers.get(&timer).map(|keys| keys.contains(&key)).unwrap_or(false) {
+                                timers.rmw(timer.clone(), vec![key.clone()]);
+                            }
+                            if capabilities.iter().all(|other| other.time() != &timer) {
+                                capabilities.push(capability.delayed(&timer));
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Maps each record using the state of its key, which the logic may replace or clear.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Inspect};
+    /// use timely::dataflow::operators::keyed::KeyBy;
+    ///
+    /// timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .key_by(|x| x % 2, |key| *key)
+    ///         .map_with_state(|_key, x, seen: Option<u64>| {
+    ///             let seen = seen.unwrap_or(0) + 1;
+    ///             ((x, seen), Some(seen))
+    ///         })
+    ///         .stream()
+    ///         .inspect(|&(_key, (x, seen))| assert_eq!(seen, x / 2 + 1));
+    /// });
+    /// ```
+    pub fn map_with_state<R, S, L>(&self, logic: L) -> KeyedStream<G, K, R>
+    where
+        R: ExchangeData,
+        S: 'static+FasterValue+FasterRmw,
+        L: Fn(&K, V, Option<S>)->(R, Option<S>)+'static,
+        G::Timestamp: FasterKey,
+    {
+        let stream = self.process("MapWithState", move |key, val, context| {
+            let (result, state) = logic(key, val, context.remove("state"));
+            if let Some(state) = state {
+                context.set("state", state);
+            }
+            context.emit((key.clone(), result));
+        }, |_, _, _| { });
+        KeyedStream { stream, hash: self.hash.clone() }
+    }
+
+    /// Folds each record into an accumulation per key, producing the accumulation after each record.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Inspect};
+    /// use timely::dataflow::operators::keyed::KeyBy;
+    ///
+    /// timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .key_by(|x| x % 2, |key| *key)
+    ///         .fold(Vec::new(), |seen: &mut Vec<u64>, x| seen.push(x))
+    ///         .stream()
+    ///         .inspect(|(key, seen)| assert!(seen.iter().all(|x| x % 2 == *key)));
+    /// });
+    /// ```
+    pub fn fold<A, L>(&self, init: A, logic: L) -> KeyedStream<G, K, A>
+    where
+        A: ExchangeData+FasterValue+FasterRmw,
+        L: Fn(&mut A, V)+'static,
+        G::Timestamp: FasterKey,
+    {
+        self.map_with_state(move |_key, val, state: Option<A>| {
+            let mut accum = state.unwrap_or_else(|| init.clone());
+            logic(&mut accum, val);
+            (accum.clone(), Some(accum))
+        })
+    }
+
+    /// Combines each record with the reduction of its key, producing the reduction after each record.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::keyed::KeyBy;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     vec![3u64, 5, 4, 8].to_stream(scope)
+    ///         .key_by(|_| (), |_| 0)
+    ///         .reduce(|x, y| std::cmp::max(x, y))
+    ///         .stream()
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, vec![((), 3), ((), 5), ((), 5), ((), 8)])]);
+    /// ```
+    pub fn reduce<L>(&self, logic: L) -> KeyedStream<G, K, V>
+    where
+        V: FasterValue+FasterRmw,
+        L: Fn(V, V)->V+'static,
+        G::Timestamp: FasterKey,
+    {
+        self.map_with_state(move |_key, val, state: Option<V>| {
+            let reduced = match state {
+                Some(state) => logic(state, val),
+                None => val,
+            };
+            (reduced.clone(), Some(reduced))
+        })
+    }
+}
+
+// The earliest capability whose time the frontier has passed, if any.
+fn next_timer<T: crate::progress::Timestamp>(capabilities: &[Capability<T>], frontier: &crate::progress::frontier::MutableAntichain<T>) -> Option<usize> {
+    capabilities
+        .iter()
+        .enumerate()
+        .filter(|(_, capability)| !frontier.less_equal(capability.time()))
+        .min_by(|(_, x), (_, y)| x.time().cmp(y.time()))
+        .map(|(position, _)| position)
+}
+
+/// Managed maps holding per-key state, one per state name.
+pub struct KeyedState<S: StateBackend, K: 'static+FasterKey+Hash+Eq> {
+    handle: StateHandle<S>,
+    maps: HashMap<String, Box<Any>>,
+    phantom: ::std::marker::PhantomData<K>,
+}
+
+impl<S: StateBackend, K: 'static+FasterKey+Hash+Eq> KeyedState<S, K> {
+    fn new(handle: StateHandle<S>) -> Self {
+        KeyedState {
+            handle,
+            maps: HashMap::new(),
+            phantom: ::std::marker::PhantomData,
+        }
+    }
+
+    fn map<V: 'static+FasterValue+FasterRmw>(&mut self, name: &str) -> &mut Box<ManagedMap<K, V>> {
+        let handle = &self.handle;
+        self.maps
+            .entry(name.to_owned())
+            .or_insert_with(|| Box::new(handle.get_managed_map::<K, V>(name)) as Box<Any>)
+            .downcast_mut::<Box<ManagedMap<K, V>>>()
+            .unwrap_or_else(|| panic!("State {} used with values of different types", name))
+    }
+}
+
+/// The view of an operator's state and timers from the key being processed.
+pub struct KeyContext<'a, G: Scope, K: 'static+FasterKey+Hash+Eq, R> {
+    key: &'a K,
+    time: &'a G::Timestamp,
+    state: &'a mut KeyedState<G::StateBackend, K>,
+    timers: &'a mut Vec<G::Timestamp>,
+    output: &'a mut Vec<R>,
+}
+
+impl<'a, G: Scope, K: 'static+FasterKey+Hash+Eq+Clone, R> KeyContext<'a, G, K, R> {
+    /// The key being processed.
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    /// The time of the record or timer being processed.
+    pub fn time(&self) -> &G::Timestamp {
+        self.time
+    }
+
+    /// The value of the state `name` for the current key.
+    pub fn get<V: 'static+FasterValue+FasterRmw>(&mut self, name: &str) -> Option<Rc<V>> {
+        self.state.map::<V>(name).get(self.key)
+    }
+
+    /// Sets the value of the state `name` for the current key.
+    pub fn set<V: 'static+FasterValue+FasterRmw>(&mut self, name: &str, value: V) {
+        self.state.map::<V>(name).insert(self.key.clone(), value);
+    }
+
+    /// Updates the value of the state `name` for the current key with `modification`.
+    pub fn rmw<V: 'static+FasterValue+FasterRmw>(&mut self, name: &str, modification: V) {
+        self.state.map::<V>(name).rmw(self.key.clone(), modification);
+    }
+
+    /// Removes and returns the value of the state `name` for the current key.
+    pub fn remove<V: 'static+FasterValue+FasterRmw>(&mut self, name: &str) -> Option<V> {
+        self.state.map::<V>(name).remove(self.key)
+    }
+
+    /// Requests that the timer logic be invoked for the current key once the frontier passes `time`.
+    pub fn register_timer(&mut self, time: G::Timestamp) {
+        self.timers.push(time);
+    }
+
+    /// Produces `record` at the time being processed.
+    pub fn emit(&mut self, record: R) {
+        self.output.push(record);
+    }
+}
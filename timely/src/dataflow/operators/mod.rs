@@ -25,12 +25,21 @@ pub use self::probe::Probe;
 pub use self::to_stream::ToStream;
 pub use self::capture::Capture;
 pub use self::branch::{Branch, BranchWhen};
+pub use self::watermark::Watermark;
 
 pub use self::generic::Operator;
 pub use self::generic::{Notificator, FrontierNotificator};
 
 pub use self::reclock::Reclock;
-pub use self::count::Accumulate;
+pub use self::count::{Accumulate, ManagedAccumulate};
+pub use self::join::Join;
+pub use self::interval_join::IntervalJoin;
+pub use self::topk::{TopK, WindowedTopK, HeavyHitters};
+pub use self::sketch::Sketches;
+pub use self::dedup::Dedup;
+pub use self::enrich::Enrich;
+pub use self::iterate::Iterate;
+pub use self::sort::SortByKey;
 
 pub mod enterleave;
 pub mod input;
@@ -49,8 +58,20 @@ pub mod probe;
 pub mod to_stream;
 pub mod capture;
 pub mod branch;
+pub mod watermark;
 
 pub mod aggregation;
+pub mod window;
+pub mod keyed;
+pub mod join;
+pub mod topk;
+pub mod sketch;
+pub mod dedup;
+pub mod cep;
+pub mod enrich;
+pub mod iterate;
+pub mod sort;
+pub mod interval_join;
 pub mod generic;
 
 pub mod reclock;
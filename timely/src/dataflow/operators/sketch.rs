@@ -0,0 +1,388 @@
+//! Mergeable sketches summarizing the records of each time.
+//!
+//! The `Sketches` operator inserts the records of each time into a sketch on each worker, and merges
+//! the sketches of all workers on worker zero. Sketches awaiting the completion of their time are held
+//! in a `ManagedMap` keyed by time, and so by the scope's state backend.
+//!
+//! Three sketches are provided: `HyperLogLog` estimates the number of distinct records, `KllSketch`
+//! estimates quantiles, and `CountMinSketch` estimates the frequencies of records.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::{Data, ExchangeData};
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::{Exchange, ParallelizationContract, Pipeline};
+use crate::dataflow::operators::FrontierNotificator;
+use crate::dataflow::operators::generic::operator::Operator;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+
+/// A summary of records that can be merged with summaries of other records.
+pub trait Sketch<D>: Clone {
+    /// Adds a record to the sketch.
+    fn insert(&mut self, record: &D);
+    /// Adds the records summarized by `other` to the sketch.
+    fn merge(&mut self, other: &Self);
+}
+
+/// Extension trait for sketching the records of each time.
+pub trait Sketches<G: Scope, D: Data> where G::Timestamp: FasterKey {
+    /// Produces, for each time with records, a sketch of the records of all workers at that time,
+    /// starting from the empty sketch `empty`. The sketch is produced by worker zero.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Sketches, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::sketch::HyperLogLog;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..1000).to_stream(scope)
+    ///         .map(|x| x % 100)
+    ///         .sketch(HyperLogLog::new(12))
+    ///         .map(|sketch| sketch.estimate())
+    ///         .capture()
+    /// });
+    ///
+    /// let estimate = captured.extract()[0].1[0];
+    /// assert!(95 <= estimate && estimate <= 105);
+    /// ```
+    fn sketch<S>(&self, empty: S) -> Stream<G, S> where S: Sketch<D>+ExchangeData+FasterValue+FasterRmw;
+}
+
+impl<G: Scope, D: Data> Sketches<G, D> for Stream<G, D> where G::Timestamp: FasterKey {
+    fn sketch<S>(&self, empty: S) -> Stream<G, S> where S: Sketch<D>+ExchangeData+FasterValue+FasterRmw {
+        let local = fold_sketches(self, empty.clone(), Pipeline, "Sketch", |sketch: &mut S, record| sketch.insert(&record));
+        fold_sketches(&local, empty, Exchange::new(|_| 0), "MergeSketches", |sketch: &mut S, other| sketch.merge(&other))
+    }
+}
+
+/// Folds the records of each time into a sketch, produced once the time completes.
+fn fold_sketches<G, D, S, P, F>(stream: &Stream<G, D>, empty: S, pact: P, name: &str, fold: F) -> Stream<G, S>
+where
+    G: Scope,
+    G::Timestamp: FasterKey,
+    D: Data,
+    S: ExchangeData+FasterValue+FasterRmw,
+    P: ParallelizationContract<G::Timestamp, D>,
+    F: Fn(&mut S, D)+'static,
+{
+    stream.unary_frontier(pact, name, move |_capability, _info, state_handle| {
+
+        // time -> sketch of the time, while not yet complete
+        let mut sketches = state_handle.get_managed_map::<G::Timestamp, S>("sketches");
+
+        let mut notificator = FrontierNotificator::new();
+        let mut vector = Vec::new();
+
+        move |input, output| {
+
+            while let Some((time, data)) = input.next() {
+                data.swap(&mut vector);
+                let mut sketch = sketches.remove(time.time()).unwrap_or_else(|| empty.clone());
+                for record in vector.drain(..) {
+                    fold(&mut sketch, record);
+                }
+                sketches.insert(time.time().clone(), sketch);
+                notificator.notify_at(time.retain());
+            }
+
+            notificator.for_each(&[input.frontier()], |time, _| {
+                if let Some(sketch) = sketches.remove(time.time()) {
+                    output.session(&time).give(sketch);
+                }
+            });
+        }
+    })
+}
+
+fn hash_of<D: Hash>(seed: usize, record: &D) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    record.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Estimates the number of distinct records, with a relative standard error of `1.04 / sqrt(2^precision)`.
+#[derive(Clone, Debug, PartialEq, Eq, Abomonation, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Allocates an empty sketch with `2^precision` registers, for a precision from 4 to 16.
+    pub fn new(precision: u8) -> Self {
+        assert!(4 <= precision && precision <= 16, "HyperLogLog precision must be between 4 and 16");
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Estimates the number of distinct records inserted.
+    pub fn estimate(&self) -> u64 {
+        let registers = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / registers),
+        };
+        let sum: f64 = self.registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+        let estimate = alpha * registers * registers / sum;
+
+        // small cardinalities are better estimated by counting empty registers.
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * registers && empty > 0 {
+            (registers * (registers / empty as f64).ln()).round() as u64
+        }
+        else {
+            estimate.round() as u64
+        }
+    }
+}
+
+impl<D: Hash> Sketch<D> for HyperLogLog {
+    fn insert(&mut self, record: &D) {
+        let hash = hash_of(0, record);
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        assert_eq!(self.precision, other.precision, "HyperLogLog sketches must have the same precision to merge");
+        for (rank, other_rank) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if *rank < *other_rank {
+                *rank = *other_rank;
+            }
+        }
+    }
+}
+
+impl FasterRmw for HyperLogLog {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut merged = self.clone();
+        Sketch::<()>::merge(&mut merged, &modification);
+        merged
+    }
+}
+
+/// Estimates quantiles of ordered records, following the KLL sketch of Karnin, Lang and Liberty.
+///
+/// Records are held in levels of compactors, where a record at level `h` stands for `2^h` inserted
+/// records. A full level is compacted by sorting it and promoting every other record to the next level.
+#[derive(Clone, Debug, PartialEq, Eq, Abomonation, Serialize, Deserialize)]
+pub struct KllSketch<D> {
+    k: usize,
+    levels: Vec<Vec<D>>,
+    count: u64,
+    compactions: u64,
+}
+
+impl<D: Ord+Clone> KllSketch<D> {
+    /// Allocates an empty sketch whose largest compactor holds `k` records.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::sketch::{KllSketch, Sketch};
+    ///
+    /// let mut sketch = KllSketch::new(200);
+    /// for x in 0u64 .. 10000 {
+    ///     sketch.insert(&x);
+    /// }
+    ///
+    /// let median = sketch.quantile(0.5).unwrap();
+    /// assert!(4500 <= median && median <= 5500);
+    /// assert_eq!(sketch.count(), 10000);
+    /// ```
+    pub fn new(k: usize) -> Self {
+        assert!(k >= 8, "KllSketch must have compactors of at least 8 records");
+        KllSketch {
+            k,
+            levels: vec![Vec::new()],
+            count: 0,
+            compactions: 0,
+        }
+    }
+
+    /// The number of records inserted.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Estimates the record of rank `fraction * count`, or `None` if no records were inserted.
+    pub fn quantile(&self, fraction: f64) -> Option<D> {
+        let weighted = self.weighted();
+        let total: u64 = weighted.iter().map(|&(_, weight)| weight).sum();
+        let target = (fraction.max(0.0).min(1.0) * total as f64).ceil() as u64;
+        let mut seen = 0;
+        for (record, weight) in weighted {
+            seen += weight;
+            if seen >= target {
+                return Some(record.clone());
+            }
+        }
+        None
+    }
+
+    /// Estimates the fraction of inserted records less than or equal to `record`.
+    pub fn rank(&self, record: &D) -> f64 {
+        let weighted = self.weighted();
+        let total: u64 = weighted.iter().map(|&(_, weight)| weight).sum();
+        let below: u64 = weighted.iter().filter(|(other, _)| *other <= record).map(|&(_, weight)| weight).sum();
+        if total == 0 { 0.0 } else { below as f64 / total as f64 }
+    }
+
+    // The retained records in order, with the number of inserted records each stands for.
+    fn weighted(&self) -> Vec<(&D, u64)> {
+        let mut weighted = self.levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, records)| records.iter().map(move |record| (record, 1u64 << level)))
+            .collect::<Vec<_>>();
+        weighted.sort_by(|x, y| x.0.cmp(y.0));
+        weighted
+    }
+
+    fn capacity(&self, level: usize) -> usize {
+        let depth = (self.levels.len() - level - 1) as i32;
+        ::std::cmp::max(2, (self.k as f64 * (2.0f64 / 3.0).powi(depth)).ceil() as usize)
+    }
+
+    fn compress(&mut self) {
+        let mut level = 0;
+        while level < self.levels.len() {
+            if self.levels[level].len() >= self.capacity(level) {
+                if level + 1 == self.levels.len() {
+                    self.levels.push(Vec::new());
+                }
+                let mut records = ::std::mem::replace(&mut self.levels[level], Vec::new());
+                records.sort();
+                if records.len() % 2 == 1 {
+                    self.levels[level].push(records.pop().unwrap());
+                }
+                // alternate between promoting even and odd positions, to avoid a systematic bias.
+                let offset = (self.compactions % 2) as usize;
+                self.compactions += 1;
+                self.levels[level + 1].extend(records.into_iter().skip(offset).step_by(2));
+            }
+            level += 1;
+        }
+    }
+}
+
+impl<D: Ord+Clone> Sketch<D> for KllSketch<D> {
+    fn insert(&mut self, record: &D) {
+        self.levels[0].push(record.clone());
+        self.count += 1;
+        if self.levels[0].len() >= self.capacity(0) {
+            self.compress();
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        while self.levels.len() < other.levels.len() {
+            self.levels.push(Vec::new());
+        }
+        for (level, records) in other.levels.iter().enumerate() {
+            self.levels[level].extend(records.iter().cloned());
+        }
+        self.count += other.count;
+        self.compress();
+    }
+}
+
+impl<D: Ord+Clone> FasterRmw for KllSketch<D> where KllSketch<D>: FasterValue {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut merged = self.clone();
+        merged.merge(&modification);
+        merged
+    }
+}
+
+/// Estimates the frequencies of records, never underestimating them.
+///
+/// Each record increments one counter in each of `depth` rows of `width` counters, and its frequency
+/// is estimated by the least of its counters.
+#[derive(Clone, Debug, PartialEq, Eq, Abomonation, Serialize, Deserialize)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counts: Vec<u64>,
+}
+
+impl CountMinSketch {
+    /// Allocates an empty sketch of `depth` rows of `width` counters.
+    pub fn new(width: usize, depth: usize) -> Self {
+        assert!(width > 0 && depth > 0, "CountMinSketch must have at least one counter");
+        CountMinSketch {
+            width,
+            depth,
+            counts: vec![0; width * depth],
+        }
+    }
+
+    /// Allocates an empty sketch whose estimates exceed true frequencies by at most `epsilon` times
+    /// the number of records, with probability at least `1 - delta`.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::sketch::{CountMinSketch, Sketch};
+    ///
+    /// let mut sketch = CountMinSketch::with_error(0.01, 0.01);
+    /// for x in 0u64 .. 1000 {
+    ///     sketch.insert(&(x % 10));
+    /// }
+    ///
+    /// assert!(sketch.estimate(&3u64) >= 100);
+    /// assert!(sketch.estimate(&3u64) <= 110);
+    /// ```
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        assert!(epsilon > 0.0 && delta > 0.0 && delta < 1.0, "CountMinSketch error bounds must be positive");
+        let width = (::std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil() as usize;
+        CountMinSketch::new(width, ::std::cmp::max(depth, 1))
+    }
+
+    /// Estimates the number of times `record` was inserted.
+    pub fn estimate<D: Hash>(&self, record: &D) -> u64 {
+        (0 .. self.depth)
+            .map(|row| self.counts[self.index(row, record)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn index<D: Hash>(&self, row: usize, record: &D) -> usize {
+        row * self.width + (hash_of(row, record) % self.width as u64) as usize
+    }
+}
+
+impl<D: Hash> Sketch<D> for CountMinSketch {
+    fn insert(&mut self, record: &D) {
+        for row in 0 .. self.depth {
+            let index = self.index(row, record);
+            self.counts[index] += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        assert!(self.width == other.width && self.depth == other.depth, "CountMinSketch sketches must have the same shape to merge");
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += *other_count;
+        }
+    }
+}
+
+impl FasterRmw for CountMinSketch {
+    fn rmw(&self, modification: Self) -> Self {
+        let mut merged = self.clone();
+        Sketch::<()>::merge(&mut merged, &modification);
+        merged
+    }
+}
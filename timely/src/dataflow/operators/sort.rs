@@ -0,0 +1,268 @@
+//! Sorting of the records at each time by an unsigned key.
+//!
+//! Records are buffered by time, and once a time is complete they are radix sorted by their key
+//! with a `timely_sort` MSB sorter and emitted in key order. Records with equal keys keep the order
+//! in which they arrived. Each worker sorts the records it holds, so a stream should be exchanged
+//! beforehand if records must be sorted with those of other workers.
+//!
+//! When the records buffered for a time reach the spill threshold, they are sorted into a run that
+//! is written to a temporary file. Once the time is complete, the spilled runs are merged with the
+//! sorted records still in memory, reading each run back a batch at a time.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut};
+use std::slice;
+
+use abomonation::Abomonation;
+use abomonation::abomonated::Abomonated;
+use timely_sort::{MSBRadixSorter, RadixSorter, RadixSorterBase, Unsigned};
+
+use crate::Data;
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::Pipeline;
+use crate::dataflow::operators::FrontierNotificator;
+use crate::dataflow::operators::generic::operator::Operator;
+
+/// The number of records buffered for a time before they are spilled, unless otherwise specified.
+const DEFAULT_SPILL_THRESHOLD: usize = 1 << 20;
+
+/// Extension trait for `Stream`.
+pub trait SortByKey<G: Scope, D: Data+Abomonation> {
+    /// Emits the records at each time in the order of their key, as extracted by `key`, once the
+    /// time is complete. Records buffered for a time are spilled to disk every million records.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Delay, SortByKey, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     vec![(0u64, 'c'), (1, 'b'), (0, 'a'), (1, 'a'), (0, 'b')]
+    ///         .to_stream(scope)
+    ///         .delay(|x, _| x.0)
+    ///         .sort_by_key(|x| x.1 as u8)
+    ///         .capture()
+    /// });
+    ///
+    /// let times = captured.extract().into_iter().map(|(time, data)| (time, data.into_iter().map(|x| x.1).collect::<String>())).collect::<Vec<_>>();
+    /// assert_eq!(times, vec![(0, "abc".to_string()), (1, "ab".to_string())]);
+    /// ```
+    fn sort_by_key<U, F>(&self, key: F) -> Stream<G, D>
+    where
+        U: Unsigned,
+        F: Fn(&D)->U+'static;
+
+    /// Emits the records at each time in the order of their key as `sort_by_key` does, spilling
+    /// the records buffered for a time to disk whenever there are `spill_threshold` of them.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Concatenate, SortByKey, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     let streams = (0 .. 4u64).map(|part| (0 .. 25u64).map(move |x| (4 * x + part) * 7 % 100).to_stream(scope)).collect::<Vec<_>>();
+    ///     scope.concatenate(streams)
+    ///          .sort_by_key_with_threshold(|x| *x, 20)
+    ///          .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, (0 .. 100).collect::<Vec<_>>())]);
+    /// ```
+    fn sort_by_key_with_threshold<U, F>(&self, key: F, spill_threshold: usize) -> Stream<G, D>
+    where
+        U: Unsigned,
+        F: Fn(&D)->U+'static;
+}
+
+impl<G: Scope, D: Data+Abomonation> SortByKey<G, D> for Stream<G, D> {
+
+    fn sort_by_key<U, F>(&self, key: F) -> Stream<G, D>
+    where
+        U: Unsigned,
+        F: Fn(&D)->U+'static,
+    {
+        self.sort_by_key_with_threshold(key, DEFAULT_SPILL_THRESHOLD)
+    }
+
+    fn sort_by_key_with_threshold<U, F>(&self, key: F, spill_threshold: usize) -> Stream<G, D>
+    where
+        U: Unsigned,
+        F: Fn(&D)->U+'static,
+    {
+        assert!(spill_threshold > 0, "Spill threshold must be at least one record");
+
+        self.unary_frontier(Pipeline, "SortByKey", move |_capability, _info, _state_handle| {
+
+            let mut sorter = MSBRadixSorter::new();
+            // time -> records buffered in memory, their number, and the runs spilled at that time
+            let mut buffers: HashMap<G::Timestamp, (Vec<Vec<D>>, usize, Vec<Run<D>>)> = HashMap::new();
+            let mut notificator = FrontierNotificator::new();
+
+            move |input, output| {
+
+                while let Some((time, data)) = input.handle.next() {
+                    let mut batch = Vec::new();
+                    data.swap(&mut batch);
+                    let (batches, records, runs) = buffers.entry(time.time().clone()).or_insert_with(|| (Vec::new(), 0, Vec::new()));
+                    *records += batch.len();
+                    batches.push(batch);
+                    if *records >= spill_threshold {
+                        sorter.sort(batches, &key);
+                        runs.push(Run::spill(batches));
+                        sorter.rebalance(batches, 256);
+                        batches.clear();
+                        *records = 0;
+                    }
+                    notificator.notify_at(time.retain());
+                }
+
+                notificator.for_each(&[input.frontier()], |time, _| {
+                    if let Some((mut batches, _, mut runs)) = buffers.remove(time.time()) {
+                        sorter.sort(&mut batches, &key);
+                        let mut session = output.session(&time);
+                        if runs.is_empty() {
+                            for mut batch in batches.drain(..) {
+                                session.give_vec(&mut batch);
+                            }
+                        }
+                        else {
+                            runs.push(Run::in_memory(batches));
+                            merge(runs, &key, |datum| session.give(datum));
+                        }
+                    }
+                });
+            }
+        })
+    }
+}
+
+/// A sorted run of records, held in memory or spilled to a temporary file.
+struct Run<D> {
+    /// Batches of the run still in memory, last batch first.
+    memory: Vec<Vec<D>>,
+    /// The file of a spilled run, and the number of batches not yet read from it.
+    file: Option<(BufReader<File>, usize)>,
+    /// Records of the current in-memory batch not yet read.
+    current: ::std::vec::IntoIter<D>,
+    /// The current batch read back from the file, and the number of its records already read.
+    spilled: Option<(Abomonated<Vec<D>, AlignedBytes>, usize)>,
+}
+
+impl<D: Data+Abomonation> Run<D> {
+
+    fn in_memory(mut batches: Vec<Vec<D>>) -> Self {
+        batches.reverse();
+        Run {
+            memory: batches,
+            file: None,
+            current: Vec::new().into_iter(),
+            spilled: None,
+        }
+    }
+
+    /// Writes sorted batches to a temporary file, which is removed once the run is dropped.
+    fn spill(batches: &[Vec<D>]) -> Self {
+        let file = ::tempfile::tempfile().expect("Failed to create spill file");
+        let mut writer = BufWriter::new(file);
+        for batch in batches.iter() {
+            write_batch(&mut writer, batch);
+        }
+        let mut file = writer.into_inner().expect("Failed to write spill file");
+        file.seek(SeekFrom::Start(0)).expect("Failed to rewind spill file");
+        Run {
+            memory: Vec::new(),
+            file: Some((BufReader::new(file), batches.len())),
+            current: Vec::new().into_iter(),
+            spilled: None,
+        }
+    }
+}
+
+impl<D: Data+Abomonation> Iterator for Run<D> {
+    type Item = D;
+    fn next(&mut self) -> Option<D> {
+        loop {
+            if let Some(datum) = self.current.next() {
+                return Some(datum);
+            }
+            if let Some((ref batch, ref mut read)) = self.spilled {
+                if *read < batch.len() {
+                    *read += 1;
+                    return Some(batch[*read - 1].clone());
+                }
+            }
+            match self.file {
+                Some((ref mut reader, ref mut remaining)) if *remaining > 0 => {
+                    *remaining -= 1;
+                    self.spilled = Some((read_batch(reader), 0));
+                },
+                _ => {
+                    self.spilled = None;
+                    self.current = self.memory.pop()?.into_iter();
+                },
+            }
+        }
+    }
+}
+
+fn write_batch<D: Abomonation, W: Write>(writer: &mut W, batch: &Vec<D>) {
+    let length = ::abomonation::measure(batch) as u64;
+    writer.write_all(&length.to_le_bytes()).expect("Failed to write spill file");
+    unsafe { ::abomonation::encode(batch, writer).expect("Spilled batch abomonation/write failed"); }
+}
+
+/// Reads a batch back into a buffer aligned for `u64`, as the communication layer does, and decodes it in place.
+fn read_batch<D: Data+Abomonation, R: Read>(reader: &mut R) -> Abomonated<Vec<D>, AlignedBytes> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes).expect("Failed to read spill file");
+    let length = u64::from_le_bytes(bytes) as usize;
+    let mut buffer = AlignedBytes { words: vec![0u64; length / 8 + 1], length };
+    reader.read_exact(&mut buffer[..]).expect("Failed to read spill file");
+    unsafe { Abomonated::new(buffer) }.expect("Spilled batch abomonation/read failed")
+}
+
+/// Initialised bytes backed by `u64` words, so that records decoded from them are aligned.
+struct AlignedBytes {
+    words: Vec<u64>,
+    length: usize,
+}
+
+impl Deref for AlignedBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const u8, self.length) }
+    }
+}
+
+impl DerefMut for AlignedBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.length) }
+    }
+}
+
+/// Merges sorted runs by key, preferring earlier runs among records with equal keys.
+fn merge<D: Data+Abomonation, U: Unsigned, F: Fn(&D)->U, G: FnMut(D)>(mut runs: Vec<Run<D>>, key: &F, mut give: G) {
+
+    let mut heads = Vec::with_capacity(runs.len());
+    let mut heap = BinaryHeap::new();
+    for (index, run) in runs.iter_mut().enumerate() {
+        let head = run.next();
+        if let Some(ref datum) = head {
+            heap.push(Reverse((key(datum), index)));
+        }
+        heads.push(head);
+    }
+
+    while let Some(Reverse((_, index))) = heap.pop() {
+        let head = runs[index].next();
+        if let Some(ref datum) = head {
+            heap.push(Reverse((key(datum), index)));
+        }
+        let datum = ::std::mem::replace(&mut heads[index], head).expect("Merged run has no head");
+        give(datum);
+    }
+}
@@ -0,0 +1,313 @@
+//! Top-K and heavy-hitter selection per time and per window.
+//!
+//! The exact operators first aggregate the weight of each key on the worker its key is routed to,
+//! then select the top `k` keys of each time on each worker, and finally merge the partial rankings
+//! of all workers on worker zero. Only `k` candidates per time and worker cross the final exchange.
+//!
+//! The approximate operator summarizes the keys of each time with the Space-Saving algorithm on each
+//! worker, and merges the summaries on worker zero, without routing records by key.
+
+use std::hash::Hash;
+use std::ops::AddAssign;
+
+use crate::{Data, ExchangeData};
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::{Exchange, ParallelizationContract, Pipeline};
+use crate::dataflow::operators::{FrontierNotificator, Map};
+use crate::dataflow::operators::aggregation::ManagedAggregate;
+use crate::dataflow::operators::generic::operator::Operator;
+use crate::dataflow::operators::window::{Window, Windows};
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+
+/// Extension trait for selecting the heaviest keys of each time.
+pub trait TopK<G: Scope, K: ExchangeData+Hash+Eq+FasterKey, W: ExchangeData+Ord+Default+AddAssign+FasterValue+FasterRmw> where G::Timestamp: FasterKey {
+    /// Produces, for each time, the `k` keys with the greatest total weight at that time, with their
+    /// weights, in order of decreasing weight. Keys of equal weight are ranked arbitrarily.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, TopK, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .map(|x| (x % 4, x))
+    ///         .top_k(2, |key| *key)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(0, vec![vec![(1, 15), (0, 12)]])]);
+    /// ```
+    fn top_k<H: Fn(&K)->u64+'static>(&self, k: usize, hash: H) -> Stream<G, Vec<(K, W)>>;
+}
+
+impl<G: Scope, K, W> TopK<G, K, W> for Stream<G, (K, W)>
+where
+    G::Timestamp: FasterKey,
+    K: ExchangeData+Hash+Eq+FasterKey,
+    W: ExchangeData+Ord+Default+AddAssign+FasterValue+FasterRmw,
+{
+    fn top_k<H: Fn(&K)->u64+'static>(&self, k: usize, hash: H) -> Stream<G, Vec<(K, W)>> {
+        let weights = self
+            .managed_aggregate(|_key, weight, total: &mut W| *total += weight, |key, total| ((), key, total), hash);
+        merge_rankings(&weights, k)
+            .map(|((), ranking)| ranking)
+    }
+}
+
+/// Extension trait for selecting the heaviest keys of each event-time window.
+pub trait WindowedTopK<G: Scope<Timestamp=u64>, K: ExchangeData+Hash+Eq+FasterKey, W: ExchangeData+Ord+Default+AddAssign+FasterValue+FasterRmw> {
+    /// Produces, for each window of `size` event time units starting every `slide` units, the `k`
+    /// keys with the greatest total weight in the window, in order of decreasing weight.
+    ///
+    /// Windows are formed and closed as by `Windows::sliding_window`, and each ranking is produced
+    /// at the time the weights of its window are.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, WindowedTopK, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::window::Window;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .map(|x| (x % 3, x))
+    ///         .windowed_top_k(1, 5, 5, |&(_, x)| x, |key| *key)
+    ///         .capture()
+    /// });
+    ///
+    /// assert_eq!(captured.extract(), vec![(4, vec![(Window::new(0, 5), vec![(1, 5)])]),
+    ///                                     (9, vec![(Window::new(5, 10), vec![(0, 15)])])]);
+    /// ```
+    fn windowed_top_k<E, H>(&self, k: usize, size: u64, slide: u64, event_time: E, hash: H) -> Stream<G, (Window, Vec<(K, W)>)>
+    where
+        E: Fn(&(K, W))->u64+'static,
+        H: Fn(&K)->u64+'static;
+}
+
+impl<G: Scope<Timestamp=u64>, K, W> WindowedTopK<G, K, W> for Stream<G, (K, W)>
+where
+    K: ExchangeData+Hash+Eq+FasterKey,
+    W: ExchangeData+Ord+Default+AddAssign+FasterValue+FasterRmw,
+{
+    fn windowed_top_k<E, H>(&self, k: usize, size: u64, slide: u64, event_time: E, hash: H) -> Stream<G, (Window, Vec<(K, W)>)>
+    where
+        E: Fn(&(K, W))->u64+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        let weights = self
+            .sliding_window(size, slide, event_time, |_key, weight, total: &mut W| *total += weight, hash)
+            .map(|(key, window, total)| (window, key, total));
+        merge_rankings(&weights, k)
+    }
+}
+
+/// Ranks the weighted keys of each group at each time, first on each worker and then on worker zero.
+fn merge_rankings<G, B, K, W>(weights: &Stream<G, (B, K, W)>, k: usize) -> Stream<G, (B, Vec<(K, W)>)>
+where
+    G: Scope,
+    G::Timestamp: FasterKey,
+    B: ExchangeData+Ord+FasterValue,
+    K: ExchangeData+FasterValue,
+    W: ExchangeData+Ord+FasterValue,
+{
+    let local = rank(weights, k, Pipeline, "LocalTopK")
+        .flat_map(|(group, ranking)| ranking.into_iter().map(move |(key, weight)| (group.clone(), key, weight)));
+    rank(&local, k, Exchange::new(|_| 0), "TopK")
+}
+
+/// Retains the `k` heaviest keys of each group at each time, and produces them once the time completes.
+fn rank<G, B, K, W, P>(weights: &Stream<G, (B, K, W)>, k: usize, pact: P, name: &str) -> Stream<G, (B, Vec<(K, W)>)>
+where
+    G: Scope,
+    G::Timestamp: FasterKey,
+    B: ExchangeData+Ord+FasterValue,
+    K: ExchangeData+FasterValue,
+    W: ExchangeData+Ord+FasterValue,
+    P: ParallelizationContract<G::Timestamp, (B, K, W)>,
+{
+    weights.unary_frontier(pact, name, move |_capability, _info, state_handle| {
+
+        // time -> candidates for the top `k` keys of each group, sorted by group and decreasing weight
+        let mut candidates = state_handle.get_managed_map::<G::Timestamp, Vec<(B, K, W)>>("candidates");
+
+        let mut notificator = FrontierNotificator::new();
+        let mut vector = Vec::new();
+
+        move |input, output| {
+
+            while let Some((time, data)) = input.next() {
+                data.swap(&mut vector);
+                let mut top = candidates.remove(time.time()).unwrap_or_default();
+                top.extend(vector.drain(..));
+                truncate_groups(&mut top, k);
+                candidates.insert(time.time().clone(), top);
+                notificator.notify_at(time.retain());
+            }
+
+            notificator.for_each(&[input.frontier()], |time, _| {
+                if let Some(top) = candidates.remove(time.time()) {
+                    let mut session = output.session(&time);
+                    let mut rankings: Vec<(B, Vec<(K, W)>)> = Vec::new();
+                    for (group, key, weight) in top {
+                        if rankings.last().map(|(last, _)| *last != group).unwrap_or(true) {
+                            rankings.push((group.clone(), Vec::new()));
+                        }
+                        rankings.last_mut().unwrap().1.push((key, weight));
+                    }
+                    session.give_iterator(rankings.into_iter());
+                }
+            });
+        }
+    })
+}
+
+// Sorts candidates by group and decreasing weight, and retains the first `k` of each group.
+fn truncate_groups<B: Ord, K, W: Ord>(candidates: &mut Vec<(B, K, W)>, k: usize) {
+    candidates.sort_by(|x, y| x.0.cmp(&y.0).then_with(|| y.2.cmp(&x.2)));
+    let mut retained: Vec<(B, K, W)> = Vec::with_capacity(candidates.len());
+    let mut group_len = 0;
+    for candidate in candidates.drain(..) {
+        if retained.last().map(|last| last.0 != candidate.0).unwrap_or(true) {
+            group_len = 0;
+        }
+        if group_len < k {
+            retained.push(candidate);
+        }
+        group_len += 1;
+    }
+    *candidates = retained;
+}
+
+/// Extension trait for estimating the most frequent records of each time.
+pub trait HeavyHitters<G: Scope, K: ExchangeData+Eq+FasterValue> where G::Timestamp: FasterKey {
+    /// Produces, for each time, estimates of the most frequent records at that time, as triples
+    /// `(record, count, error)` in order of decreasing count.
+    ///
+    /// Each worker tracks at most `capacity` records per time with the Space-Saving algorithm, and
+    /// the summaries are merged on worker zero. The true count of a record lies between `count - error`
+    /// and `count`, and any record occurring more than `n / capacity` times among the `n` records of a
+    /// time is reported.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, HeavyHitters, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..100).to_stream(scope)
+    ///         .map(|x| if x % 2 == 0 { 0 } else { x })
+    ///         .heavy_hitters(4)
+    ///         .capture()
+    /// });
+    ///
+    /// let extracted = captured.extract();
+    /// assert_eq!(extracted.len(), 1);
+    /// assert_eq!(extracted[0].1[0][0].0, 0);
+    /// assert!(extracted[0].1[0][0].1 >= 50);
+    /// ```
+    fn heavy_hitters(&self, capacity: usize) -> Stream<G, Vec<(K, u64, u64)>>;
+}
+
+impl<G: Scope, K: ExchangeData+Eq+FasterValue> HeavyHitters<G, K> for Stream<G, K> where G::Timestamp: FasterKey {
+    fn heavy_hitters(&self, capacity: usize) -> Stream<G, Vec<(K, u64, u64)>> {
+        assert!(capacity > 0, "HeavyHitters must track at least one record");
+        let local = summarize(self, Pipeline, "LocalHeavyHitters", move |summary, records: Vec<K>| {
+            for record in records {
+                space_saving_insert(summary, record, capacity);
+            }
+        });
+        summarize(&local, Exchange::new(|_| 0), "HeavyHitters", move |summary, summaries: Vec<Vec<(K, u64, u64)>>| {
+            for other in summaries {
+                space_saving_merge(summary, other, capacity);
+            }
+        })
+        .map(|mut summary| {
+            summary.sort_by(|x, y| y.1.cmp(&x.1));
+            summary
+        })
+    }
+}
+
+/// Folds the records of each time into a Space-Saving summary, produced once the time completes.
+fn summarize<G, D, K, P, F>(stream: &Stream<G, D>, pact: P, name: &str, fold: F) -> Stream<G, Vec<(K, u64, u64)>>
+where
+    G: Scope,
+    G::Timestamp: FasterKey,
+    D: Data,
+    K: ExchangeData+Eq+FasterValue,
+    P: ParallelizationContract<G::Timestamp, D>,
+    F: Fn(&mut Vec<(K, u64, u64)>, Vec<D>)+'static,
+{
+    stream.unary_frontier(pact, name, move |_capability, _info, state_handle| {
+
+        // time -> (record, count, error) counters of the records at that time
+        let mut summaries = state_handle.get_managed_map::<G::Timestamp, Vec<(K, u64, u64)>>("summaries");
+
+        let mut notificator = FrontierNotificator::new();
+        let mut vector = Vec::new();
+
+        move |input, output| {
+
+            while let Some((time, data)) = input.next() {
+                data.swap(&mut vector);
+                let mut summary = summaries.remove(time.time()).unwrap_or_default();
+                fold(&mut summary, vector.drain(..).collect());
+                summaries.insert(time.time().clone(), summary);
+                notificator.notify_at(time.retain());
+            }
+
+            notificator.for_each(&[input.frontier()], |time, _| {
+                if let Some(summary) = summaries.remove(time.time()) {
+                    output.session(&time).give(summary);
+                }
+            });
+        }
+    })
+}
+
+// Counts `record`, replacing the least counted record if the summary is full.
+fn space_saving_insert<K: Eq>(summary: &mut Vec<(K, u64, u64)>, record: K, capacity: usize) {
+    if let Some(counter) = summary.iter_mut().find(|counter| counter.0 == record) {
+        counter.1 += 1;
+    }
+    else if summary.len() < capacity {
+        summary.push((record, 1, 0));
+    }
+    else {
+        let min = summary.iter_mut().min_by_key(|counter| counter.1).expect("Summary must not be empty");
+        *min = (record, min.1 + 1, min.1);
+    }
+}
+
+// Merges `other` into `summary`, as in "Mergeable Summaries" by Agarwal et al.
+//
+// A record missing from a full summary may have occurred as often as its least counted record, so it
+// is charged that count, as error, before the heaviest `capacity` counters are retained.
+fn space_saving_merge<K: Eq>(summary: &mut Vec<(K, u64, u64)>, other: Vec<(K, u64, u64)>, capacity: usize) {
+    let min_count = |summary: &Vec<(K, u64, u64)>| {
+        if summary.len() < capacity { 0 } else { summary.iter().map(|counter| counter.1).min().unwrap_or(0) }
+    };
+    let summary_min = min_count(summary);
+    let other_min = min_count(&other);
+
+    let mut merged = Vec::with_capacity(summary.len() + other.len());
+    let mut other = other;
+    for (record, count, error) in summary.drain(..) {
+        match other.iter().position(|counter| counter.0 == record) {
+            Some(position) => {
+                let (_, other_count, other_error) = other.swap_remove(position);
+                merged.push((record, count + other_count, error + other_error));
+            }
+            None => merged.push((record, count + other_min, error + other_min)),
+        }
+    }
+    for (record, count, error) in other {
+        merged.push((record, count + summary_min, error + summary_min));
+    }
+
+    merged.sort_by(|x, y| y.1.cmp(&x.1));
+    merged.truncate(capacity);
+    *summary = merged;
+}
@@ -0,0 +1,95 @@
+//! Operators assigning event-time timestamps to out-of-order records.
+
+use std::collections::HashMap;
+
+use crate::Data;
+use crate::progress::frontier::Antichain;
+use crate::dataflow::channels::pact::Pipeline;
+use crate::dataflow::operators::generic::builder_rc::OperatorBuilder;
+use crate::dataflow::{Scope, Stream};
+
+/// Extension trait for `Stream`.
+pub trait Watermark<S: Scope<Timestamp=u64>, D: Data> {
+    /// Re-timestamps records with their event time, tolerating records out of order by up to
+    /// `allowed_lateness` event time units.
+    ///
+    /// The operator tracks a watermark trailing the greatest event time seen by `allowed_lateness`,
+    /// and holds a capability at the watermark rather than at the times of its input. Records at or
+    /// beyond the watermark are produced on the first stream at their event time, whose frontier
+    /// advances with the watermark independently of the input frontier. Records behind the watermark
+    /// are produced on the second stream at their original time.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Watermark, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    ///
+    /// let (on_time, late) = timely::example(|scope| {
+    ///     let (on_time, late) = vec![5u64, 3, 9, 1, 10]
+    ///         .to_stream(scope)
+    ///         .watermark(|x| *x, 2);
+    ///     (on_time.capture(), late.capture())
+    /// });
+    ///
+    /// assert_eq!(on_time.extract(), vec![(3, vec![3]), (5, vec![5]), (9, vec![9]), (10, vec![10])]);
+    /// assert_eq!(late.extract(), vec![(0, vec![1])]);
+    /// ```
+    fn watermark(&self, event_time: impl Fn(&D)->u64+'static, allowed_lateness: u64) -> (Stream<S, D>, Stream<S, D>);
+}
+
+impl<S: Scope<Timestamp=u64>, D: Data> Watermark<S, D> for Stream<S, D> {
+    fn watermark(&self, event_time: impl Fn(&D)->u64+'static, allowed_lateness: u64) -> (Stream<S, D>, Stream<S, D>) {
+
+        let mut builder = OperatorBuilder::new("Watermark".to_owned(), self.scope());
+
+        // the on-time output is disconnected from the input, as records may move to earlier times.
+        let (mut on_time, on_time_stream) = builder.new_output();
+        let (mut late, late_stream) = builder.new_output();
+        let mut input = builder.new_input_connection(self, Pipeline, vec![Antichain::new(), Antichain::from_elem(Default::default())]);
+
+        builder.build(move |mut capabilities| {
+            capabilities.truncate(1);
+            let mut watermark = capabilities.pop();
+            let mut stash = HashMap::new();
+            let mut vector = Vec::new();
+
+            move |frontiers| {
+                let mut on_time_handle = on_time.activate();
+                let mut late_handle = late.activate();
+
+                input.for_each(|time, data| {
+                    data.swap(&mut vector);
+                    if let Some(capability) = watermark.as_mut() {
+                        let mut late_session = late_handle.session(&time);
+                        let mut mark = *capability.time();
+                        for datum in vector.drain(..) {
+                            let event_time = event_time(&datum);
+                            if event_time < mark {
+                                late_session.give(datum);
+                            }
+                            else {
+                                stash.entry(event_time).or_insert_with(Vec::new).push(datum);
+                                mark = ::std::cmp::max(mark, event_time.saturating_sub(allowed_lateness));
+                            }
+                        }
+                        for (event_time, mut records) in stash.drain() {
+                            on_time_handle.session(&capability.delayed(&event_time)).give_vec(&mut records);
+                        }
+                        capability.downgrade(&mark);
+                    }
+                    else {
+                        // the watermark has been released, so no record can be on time.
+                        late_handle.session(&time).give_vec(&mut vector);
+                    }
+                });
+
+                // once the input is exhausted, no further records can be on time.
+                if frontiers[0].is_empty() {
+                    watermark = None;
+                }
+            }
+        });
+
+        (on_time_stream, late_stream)
+    }
+}
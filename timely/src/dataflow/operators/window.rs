@@ -0,0 +1,310 @@
+//! Event-time windows over keyed streams.
+//!
+//! The operators in this module assign each `(key, val)` record to windows according to an event
+//! time extracted from the record, and fold the record into the aggregate of each of its windows.
+//! Aggregates live in managed state from the first record of a window until the window closes.
+//!
+//! A window `[start, end)` closes once the input frontier has passed `end - 1`, at which point its
+//! aggregate is emitted at time `end - 1`, or at the earliest time of its records if that is later.
+//! This relies on records carrying an event time no earlier than their timestamp, as produced by
+//! the `Watermark` operator; records assigned to a window that has already closed are dropped.
+
+use std::collections::BTreeMap;
+use std::hash::Hash;
+
+use crate::{Data, ExchangeData};
+use crate::dataflow::{Stream, Scope};
+use crate::dataflow::channels::pact::Exchange;
+use crate::dataflow::operators::Capability;
+use crate::dataflow::operators::generic::operator::Operator;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+
+/// The bounds `[start, end)` of an event-time window.
+#[derive(Abomonation, Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Window {
+    /// The first event time contained in the window.
+    pub start: u64,
+    /// The first event time following the window.
+    pub end: u64,
+}
+
+impl Window {
+    /// Allocates a new window from its bounds.
+    pub fn new(start: u64, end: u64) -> Self {
+        assert!(start < end, "Window must not be empty");
+        Window { start, end }
+    }
+
+    /// Returns true iff `time` lies within the window.
+    pub fn contains(&self, time: u64) -> bool {
+        self.start <= time && time < self.end
+    }
+
+    /// Returns true iff the windows overlap or are adjacent, and so belong to the same session.
+    pub fn intersects(&self, other: &Window) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    /// The smallest window covering both windows.
+    pub fn cover(&self, other: &Window) -> Window {
+        Window::new(::std::cmp::min(self.start, other.start), ::std::cmp::max(self.end, other.end))
+    }
+}
+
+/// Event-time windowing of keyed streams.
+pub trait Windows<G: Scope<Timestamp=u64>, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData> {
+    /// Aggregates records into consecutive windows of `size` event time units.
+    ///
+    /// The functions `event_time` and `fold` extract the event time of a record and fold it into the
+    /// aggregate of a window, and `hash` routes keys to workers.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::window::{Windows, Window};
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..10).to_stream(scope)
+    ///         .map(|x| (x % 2, x))
+    ///         .tumbling_window(5, |&(_, x)| x, |_key, val, sum: &mut u64| *sum += val, |key| *key)
+    ///         .capture()
+    /// });
+    ///
+    /// let extracted = captured.extract();
+    /// assert_eq!(extracted, vec![(4, vec![(0, Window::new(0, 5), 6), (1, Window::new(0, 5), 4)]),
+    ///                            (9, vec![(0, Window::new(5, 10), 14), (1, Window::new(5, 10), 21)])]);
+    /// ```
+    fn tumbling_window<A, E, F, H>(&self, size: u64, event_time: E, fold: F, hash: H) -> Stream<G, (K, Window, A)>
+    where
+        A: Data+Default+FasterValue+FasterRmw,
+        E: Fn(&(K, V))->u64+'static,
+        F: Fn(&K, V, &mut A)+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        self.sliding_window(size, size, event_time, fold, hash)
+    }
+
+    /// Aggregates records into windows of `size` event time units starting every `slide` units.
+    ///
+    /// Each record is folded into the aggregate of every window containing its event time.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Map, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::window::{Windows, Window};
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     (0u64..4).to_stream(scope)
+    ///         .map(|x| ((), x))
+    ///         .sliding_window(4, 2, |&(_, x)| x, |_key, _val, count: &mut u64| *count += 1, |_| 0)
+    ///         .capture()
+    /// });
+    ///
+    /// let extracted = captured.extract();
+    /// assert_eq!(extracted, vec![(3, vec![((), Window::new(0, 4), 4)]),
+    ///                            (5, vec![((), Window::new(2, 6), 2)])]);
+    /// ```
+    fn sliding_window<A, E, F, H>(&self, size: u64, slide: u64, event_time: E, fold: F, hash: H) -> Stream<G, (K, Window, A)>
+    where
+        A: Data+Default+FasterValue+FasterRmw,
+        E: Fn(&(K, V))->u64+'static,
+        F: Fn(&K, V, &mut A)+'static,
+        H: Fn(&K)->u64+'static;
+
+    /// Aggregates records into per-key sessions, which close after `gap` event time units without records.
+    ///
+    /// Sessions brought together by a record are combined using `merge`.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Capture};
+    /// use timely::dataflow::operators::capture::Extract;
+    /// use timely::dataflow::operators::window::{Windows, Window};
+    ///
+    /// let captured = timely::example(|scope| {
+    ///     vec![(0u64, 0u64), (0, 2), (0, 10), (1, 1)].to_stream(scope)
+    ///         .session_window(3, |&(_, x)| x, |_key, _val, count: &mut u64| *count += 1, |count, other| *count += other, |key| *key)
+    ///         .capture()
+    /// });
+    ///
+    /// let extracted = captured.extract();
+    /// assert_eq!(extracted, vec![(3, vec![(1, Window::new(1, 4), 1)]),
+    ///                            (4, vec![(0, Window::new(0, 5), 2)]),
+    ///                            (12, vec![(0, Window::new(10, 13), 1)])]);
+    /// ```
+    fn session_window<A, E, F, M, H>(&self, gap: u64, event_time: E, fold: F, merge: M, hash: H) -> Stream<G, (K, Window, A)>
+    where
+        A: Data+Default+FasterValue+FasterRmw,
+        E: Fn(&(K, V))->u64+'static,
+        F: Fn(&K, V, &mut A)+'static,
+        M: Fn(&mut A, A)+'static,
+        H: Fn(&K)->u64+'static;
+}
+
+impl<G: Scope<Timestamp=u64>, K: ExchangeData+Hash+Eq+FasterKey, V: ExchangeData> Windows<G, K, V> for Stream<G, (K, V)> {
+
+    fn sliding_window<A, E, F, H>(&self, size: u64, slide: u64, event_time: E, fold: F, hash: H) -> Stream<G, (K, Window, A)>
+    where
+        A: Data+Default+FasterValue+FasterRmw,
+        E: Fn(&(K, V))->u64+'static,
+        F: Fn(&K, V, &mut A)+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        assert!(size > 0 && slide > 0, "Window size and slide must be positive");
+
+        self.unary_frontier(Exchange::new(move |&(ref k, _)| hash(k)), "SlidingWindow", |_capability, _info, state_handle| {
+
+            // (key, window) -> aggregate, and window -> keys with an aggregate in that window
+            let mut aggregates = state_handle.get_managed_map::<(K, Window), A>("aggregates");
+            let mut keys = state_handle.get_managed_map::<Window, Vec<K>>("keys");
+
+            // window end -> capability for the window's output
+            let mut capabilities: BTreeMap<u64, Capability<u64>> = BTreeMap::new();
+            // windows ending at or before this time have been emitted
+            let mut closed = 0;
+
+            let mut vector = Vec::new();
+
+            move |input, output| {
+
+                while let Some((time, data)) = input.next() {
+                    data.swap(&mut vector);
+                    for record in vector.drain(..) {
+                        let event_time = event_time(&record);
+                        let (key, val) = record;
+
+                        // the last window containing `event_time` starts at the last multiple of `slide`.
+                        let mut start = event_time - event_time % slide;
+                        loop {
+                            let window = Window::new(start, start + size);
+                            if window.contains(event_time) && window.end > closed {
+                                let entry = (key.clone(), window);
+                                let mut agg = match aggregates.remove(&entry) {
+                                    Some(agg) => agg,
+                                    None => {
+                                        keys.rmw(window, vec![key.clone()]);
+                                        Default::default()
+                                    }
+                                };
+                                fold(&key, val.clone(), &mut agg);
+                                aggregates.insert(entry, agg);
+                                hold(&mut capabilities, window.end, time.delayed(time.time()));
+                            }
+                            if start < slide || start + size <= event_time + slide { break; }
+                            start -= slide;
+                        }
+                    }
+                }
+
+                // emit windows the frontier has passed, in order of their ends
+                while let Some(end) = next_closed(&capabilities, &input.frontier().frontier()) {
+                    let capability = capabilities.remove(&end).unwrap();
+                    let window = Window::new(end.saturating_sub(size), end);
+                    if let Some(window_keys) = keys.remove(&window) {
+                        let mut session = output.session(&capability);
+                        for key in window_keys {
+                            let agg = aggregates.remove(&(key.clone(), window)).expect("Aggregate missing for window");
+                            session.give((key, window, agg));
+                        }
+                    }
+                    closed = end;
+                }
+            }
+        })
+    }
+
+    fn session_window<A, E, F, M, H>(&self, gap: u64, event_time: E, fold: F, merge: M, hash: H) -> Stream<G, (K, Window, A)>
+    where
+        A: Data+Default+FasterValue+FasterRmw,
+        E: Fn(&(K, V))->u64+'static,
+        F: Fn(&K, V, &mut A)+'static,
+        M: Fn(&mut A, A)+'static,
+        H: Fn(&K)->u64+'static,
+    {
+        assert!(gap > 0, "Session gap must be positive");
+
+        self.unary_frontier(Exchange::new(move |&(ref k, _)| hash(k)), "SessionWindow", |_capability, _info, state_handle| {
+
+            // key -> open sessions, (key, session) -> aggregate, and session end -> keys with a session ending there
+            let mut sessions = state_handle.get_managed_map::<K, Vec<Window>>("sessions");
+            let mut aggregates = state_handle.get_managed_map::<(K, Window), A>("aggregates");
+            let mut ending = state_handle.get_managed_map::<u64, Vec<K>>("ending");
+
+            let mut capabilities: BTreeMap<u64, Capability<u64>> = BTreeMap::new();
+            let mut closed = 0;
+
+            let mut vector = Vec::new();
+
+            move |input, output| {
+
+                while let Some((time, data)) = input.next() {
+                    data.swap(&mut vector);
+                    for record in vector.drain(..) {
+                        let event_time = event_time(&record);
+                        let (key, val) = record;
+
+                        let mut window = Window::new(event_time, event_time + gap);
+                        if window.end <= closed { continue; }
+
+                        // fold the record into a fresh aggregate, and merge in every session it touches
+                        let mut agg = Default::default();
+                        fold(&key, val, &mut agg);
+                        let mut open = sessions.remove(&key).unwrap_or_default();
+                        let (touched, mut untouched): (Vec<Window>, Vec<Window>) = open.drain(..).partition(|s| s.intersects(&window));
+                        for session in touched {
+                            window = window.cover(&session);
+                            let other = aggregates.remove(&(key.clone(), session)).expect("Aggregate missing for session");
+                            merge(&mut agg, other);
+                        }
+                        untouched.push(window);
+                        sessions.insert(key.clone(), untouched);
+                        aggregates.insert((key.clone(), window), agg);
+                        ending.rmw(window.end, vec![key]);
+                        hold(&mut capabilities, window.end, time.delayed(time.time()));
+                    }
+                }
+
+                while let Some(end) = next_closed(&capabilities, &input.frontier().frontier()) {
+                    let capability = capabilities.remove(&end).unwrap();
+                    if let Some(ending_keys) = ending.remove(&end) {
+                        let mut session = output.session(&capability);
+                        for key in ending_keys {
+                            // sessions since extended end later, and a key may be listed more than once.
+                            let open = sessions.remove(&key).unwrap_or_default();
+                            let (done, remaining): (Vec<Window>, Vec<Window>) = open.into_iter().partition(|s| s.end == end);
+                            if !remaining.is_empty() {
+                                sessions.insert(key.clone(), remaining);
+                            }
+                            for window in done {
+                                let agg = aggregates.remove(&(key.clone(), window)).expect("Aggregate missing for session");
+                                session.give((key.clone(), window, agg));
+                            }
+                        }
+                    }
+                    closed = end;
+                }
+            }
+        })
+    }
+}
+
+// Holds a capability for the output of windows ending at `end`, keeping the earliest one offered.
+fn hold(capabilities: &mut BTreeMap<u64, Capability<u64>>, end: u64, capability: Capability<u64>) {
+    let output_time = ::std::cmp::max(end - 1, *capability.time());
+    let held = capabilities.entry(end).or_insert_with(|| capability.delayed(&output_time));
+    if output_time < *held.time() {
+        *held = capability.delayed(&output_time);
+    }
+}
+
+// The earliest window end whose windows the frontier has passed, if any.
+fn next_closed(capabilities: &BTreeMap<u64, Capability<u64>>, frontier: &[u64]) -> Option<u64> {
+    capabilities
+        .keys()
+        .next()
+        .cloned()
+        .filter(|end| frontier.iter().all(|time| *time >= *end))
+}
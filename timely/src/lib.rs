@@ -68,6 +68,7 @@ extern crate timely_communication;
 extern crate timely_bytes;
 extern crate timely_logging;
 extern crate timely_state;
+extern crate timely_sort;
 extern crate tempfile;
 
 pub use execute::{execute, execute_directly, execute_from_args, example};
@@ -93,6 +94,8 @@ pub mod logging_core {
 /// Re-export of the `timely_state` crate.
 pub mod state {
     pub use timely_state::*;
+
+    pub mod multiversion;
 }
 
 pub mod worker;
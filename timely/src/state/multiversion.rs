@@ -0,0 +1,202 @@
+//! Managed maps retaining one version of each value per timestamp.
+//!
+//! Operators in iterative scopes, or with partially ordered timestamps, may need the value of a
+//! key as of some earlier time rather than its latest write. A `MultiVersionManagedMap` keeps a
+//! version of each value per timestamp, answers reads as of a time, and compacts versions that
+//! can no longer be observed once the frontier has passed them.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use faster_rs::{FasterKey, FasterRmw, FasterValue};
+use timely_state::primitives::ManagedMap;
+use timely_state::{StateBackend, StateHandle};
+
+use crate::progress::frontier::{Antichain, AntichainRef};
+use crate::progress::Timestamp;
+
+/// The versions of a single value, in the order in which they were written.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Versions<T, V> {
+    /// Pairs of timestamp and the value written at that timestamp.
+    pub entries: Vec<(T, V)>,
+}
+
+impl<T, V> FasterRmw for Versions<T, V>
+where
+    T: Clone+Serialize+DeserializeOwned,
+    V: Clone+Serialize+DeserializeOwned,
+{
+    fn rmw(&self, modification: Self) -> Self {
+        modification
+    }
+}
+
+/// A managed map whose reads are answered as of a timestamp.
+///
+/// A read at `time` sees the versions whose timestamps are less or equal to `time`, and returns
+/// the value of one not superseded by a greater such version. Should several such versions be
+/// incomparable, the most recently written of them is returned.
+///
+/// # Examples
+/// ```
+/// use std::rc::Rc;
+/// use timely::order::Product;
+/// use timely::progress::frontier::AntichainRef;
+/// use timely::state::{StateBackend, StateHandle};
+/// use timely::state::backends::InMemoryBackend;
+/// use timely::state::multiversion::MultiVersionManagedMap;
+///
+/// let handle = StateHandle::new(Rc::new(InMemoryBackend::new()), "0");
+/// let mut map = MultiVersionManagedMap::<_, Product<u64, u64>, _, _>::new(&handle, "versions");
+///
+/// map.insert(0u64, Product::new(0, 0), "a".to_string());
+/// map.insert(0u64, Product::new(0, 2), "b".to_string());
+/// map.insert(0u64, Product::new(1, 0), "c".to_string());
+///
+/// assert_eq!(map.get_at(&0, &Product::new(0, 1)), Some("a".to_string()));
+/// assert_eq!(map.get_at(&0, &Product::new(0, 3)), Some("b".to_string()));
+/// assert_eq!(map.get_at(&0, &Product::new(1, 1)), Some("c".to_string()));
+///
+/// // Reads now happen at (1, 0) or later, where "c" shadows "a" but not the incomparable "b".
+/// map.advance_to(AntichainRef::new(&[Product::new(1, 0)]));
+/// assert_eq!(map.versions(&0), 2);
+/// assert_eq!(map.get_at(&0, &Product::new(1, 0)), Some("c".to_string()));
+/// ```
+pub struct MultiVersionManagedMap<K, T, V, S>
+where
+    K: 'static+FasterKey+Hash+Eq+Clone,
+    T: Timestamp+Serialize+DeserializeOwned,
+    V: 'static+FasterValue+FasterRmw+Clone,
+    S: StateBackend,
+{
+    versions: Box<ManagedMap<K, Versions<T, V>>>,
+    frontier: Antichain<T>,
+    // Keys holding more than one version, the only candidates for compaction.
+    pending: HashSet<K>,
+    phantom: ::std::marker::PhantomData<S>,
+}
+
+impl<K, T, V, S> MultiVersionManagedMap<K, T, V, S>
+where
+    K: 'static+FasterKey+Hash+Eq+Clone,
+    T: Timestamp+Serialize+DeserializeOwned,
+    V: 'static+FasterValue+FasterRmw+Clone,
+    S: StateBackend,
+{
+    /// Creates a map named `name` within the namespace of `state_handle`.
+    pub fn new(state_handle: &StateHandle<S>, name: &str) -> Self {
+        MultiVersionManagedMap {
+            versions: state_handle.get_managed_map(name),
+            frontier: Antichain::from_elem(Default::default()),
+            pending: HashSet::new(),
+            phantom: ::std::marker::PhantomData,
+        }
+    }
+
+    /// Writes `value` as the version of `key` at `time`, replacing any version already at `time`.
+    ///
+    /// The time should not be earlier than the frontier last passed to `advance_to`.
+    pub fn insert(&mut self, key: K, time: T, value: V) {
+        debug_assert!(self.frontier.less_equal(&time));
+        let mut versions = self.versions.remove(&key).unwrap_or_else(|| Versions { entries: Vec::new() });
+        versions.entries.retain(|(t, _)| t != &time);
+        versions.entries.push((time, value));
+        if versions.entries.len() > 1 {
+            self.pending.insert(key.clone());
+        }
+        self.versions.insert(key, versions);
+    }
+
+    /// The value of `key` as of `time`.
+    ///
+    /// Among the versions whose times are less or equal to `time`, those not less than another one
+    /// are candidates, and the most recently written candidate is returned.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::rc::Rc;
+    /// use timely::order::Product;
+    /// use timely::state::{StateBackend, StateHandle};
+    /// use timely::state::backends::InMemoryBackend;
+    /// use timely::state::multiversion::MultiVersionManagedMap;
+    ///
+    /// let handle = StateHandle::new(Rc::new(InMemoryBackend::new()), "0");
+    /// let mut map = MultiVersionManagedMap::<_, Product<u64, u64>, _, _>::new(&handle, "versions");
+    ///
+    /// map.insert(0u64, Product::new(1, 1), "c".to_string());
+    /// map.insert(0u64, Product::new(0, 2), "b".to_string());
+    /// map.insert(0u64, Product::new(1, 0), "a".to_string());
+    ///
+    /// // "a" is superseded by "c", leaving the incomparable "c" and "b", of which "b" was written last.
+    /// assert_eq!(map.get_at(&0, &Product::new(1, 2)), Some("b".to_string()));
+    /// assert_eq!(map.get_at(&0, &Product::new(1, 1)), Some("c".to_string()));
+    /// assert_eq!(map.get_at(&0, &Product::new(1, 0)), Some("a".to_string()));
+    /// assert_eq!(map.get_at(&0, &Product::new(0, 1)), None);
+    /// ```
+    pub fn get_at(&self, key: &K, time: &T) -> Option<V> {
+        let versions = self.versions.get(key)?;
+        let readable = versions.entries.iter().filter(|(t, _)| t.less_equal(time)).collect::<Vec<_>>();
+        readable
+            .iter()
+            .filter(|(t, _)| !readable.iter().any(|(other, _)| t.less_than(other)))
+            .last()
+            .map(|(_, value)| value.clone())
+    }
+
+    /// The most recently written value of `key`, regardless of its time.
+    pub fn get_latest(&self, key: &K) -> Option<V> {
+        self.versions.get(key).and_then(|versions| versions.entries.last().map(|(_, value)| value.clone()))
+    }
+
+    /// Removes all versions of `key`.
+    pub fn remove(&mut self, key: &K) -> Option<Versions<T, V>> {
+        self.pending.remove(key);
+        self.versions.remove(key)
+    }
+
+    /// The number of versions currently retained for `key`.
+    pub fn versions(&self, key: &K) -> usize {
+        self.versions.get(key).map(|versions| versions.entries.len()).unwrap_or(0)
+    }
+
+    /// Announces that no read will happen at a time not greater or equal to an element of
+    /// `frontier`, and discards the versions that such reads can no longer observe.
+    ///
+    /// A version is discarded once another version is greater than it and less or equal to every
+    /// element of the frontier, as any read from then on finds the latter and prefers it.
+    pub fn advance_to(&mut self, frontier: AntichainRef<T>) {
+        self.frontier = Antichain::new();
+        for time in frontier.iter() {
+            self.frontier.insert(time.clone());
+        }
+
+        let pending = ::std::mem::replace(&mut self.pending, HashSet::new());
+        for key in pending {
+            if let Some(mut versions) = self.versions.remove(&key) {
+                Self::compact(&mut versions, &frontier[..]);
+                if versions.entries.len() > 1 {
+                    self.pending.insert(key.clone());
+                }
+                self.versions.insert(key, versions);
+            }
+        }
+    }
+
+    fn compact(versions: &mut Versions<T, V>, frontier: &[T]) {
+        let entries = ::std::mem::replace(&mut versions.entries, Vec::new());
+        let mut retained = Vec::with_capacity(entries.len());
+        for (index, (time, value)) in entries.iter().enumerate() {
+            let shadowed = entries.iter().enumerate().any(|(other, (later, _))| {
+                other != index && time.less_than(later) && frontier.iter().all(|f| later.less_equal(f))
+            });
+            if !shadowed {
+                retained.push((time.clone(), value.clone()));
+            }
+        }
+        versions.entries = retained;
+    }
+}
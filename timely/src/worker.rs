@@ -357,6 +357,51 @@ impl<A: Allocate> Worker<A> {
         self.dataflow_core("Dataflow", logging, Box::new(()), |_, child, state_handle| func(child, state_handle))
     }
 
+    /// Construct a new dataflow whose state backends hold at most `bytes` of state in memory.
+    ///
+    /// The budget applies to each backend of the dataflow: the one of `state_handle`, and those
+    /// the `_core` operator constructors create for each operator, unless a different budget is
+    /// set for an operator with `StateHandle::set_operator_memory_budget`. Backends that do not
+    /// bound their memory use ignore it.
+    ///
+    /// # Examples
+    /// ```
+    /// use timely::dataflow::operators::{ToStream, Inspect};
+    /// use timely::dataflow::operators::generic::Operator;
+    /// use timely::dataflow::channels::pact::Pipeline;
+    /// use timely::state::StateHandle;
+    /// use timely::state::backends::{InMemoryBackend, SpillingBackend};
+    ///
+    /// timely::execute_directly(|worker| {
+    ///     worker.dataflow_with_memory_budget::<u64,_,_,InMemoryBackend>(64 << 20, |scope, state_handle| {
+    ///         // the "Sum" operator gets a budget of its own.
+    ///         state_handle.set_operator_memory_budget("Sum", 1 << 20);
+    ///         (0u64..10)
+    ///             .to_stream(scope)
+    ///             .unary_frontier_core(Pipeline, "Sum", |_cap, _info, state_handle: StateHandle<SpillingBackend<InMemoryBackend>>| {
+    ///                 assert_eq!(state_handle.memory_budget(), Some(1 << 20));
+    ///                 let mut vector = Vec::new();
+    ///                 move |input, output| {
+    ///                     while let Some((time, data)) = input.next() {
+    ///                         data.swap(&mut vector);
+    ///                         output.session(&time).give(vector.drain(..).sum::<u64>());
+    ///                     }
+    ///                 }
+    ///             })
+    ///             .inspect(|x| assert!(*x <= 45));
+    ///     });
+    /// });
+    /// ```
+    pub fn dataflow_with_memory_budget<T, R, F, S>(&mut self, bytes: usize, func: F) -> R
+    where
+        T: Refines<()>,
+        F: FnOnce(&mut Child<Self, T, S>, &StateHandle<S>)->R,
+        S: StateBackend
+    {
+        let logging = self.logging.borrow_mut().get("timely");
+        self.dataflow_with_budget("Dataflow", logging, Box::new(()), Some(bytes), |_, child, state_handle| func(child, state_handle))
+    }
+
     /// Construct a new dataflow with specific configurations.
     ///
     /// This method constructs a new dataflow, using a name, logger, and additional
@@ -383,7 +428,17 @@ impl<A: Allocate> Worker<A> {
     ///     );
     /// });
     /// ```
-    pub fn dataflow_core<T, R, F, V, S>(&mut self, name: &str, mut logging: Option<TimelyLogger>, mut resources: V, func: F) -> R
+    pub fn dataflow_core<T, R, F, V, S>(&mut self, name: &str, logging: Option<TimelyLogger>, resources: V, func: F) -> R
+    where
+        T: Refines<()>,
+        F: FnOnce(&mut V, &mut Child<Self, T, S>, &StateHandle<S>)->R,
+        V: Any+'static,
+        S: StateBackend,
+    {
+        self.dataflow_with_budget(name, logging, resources, None, func)
+    }
+
+    fn dataflow_with_budget<T, R, F, V, S>(&mut self, name: &str, mut logging: Option<TimelyLogger>, mut resources: V, memory_budget: Option<usize>, func: F) -> R
     where
         T: Refines<()>,
         F: FnOnce(&mut V, &mut Child<Self, T, S>, &StateHandle<S>)->R,
@@ -398,8 +453,15 @@ impl<A: Allocate> Worker<A> {
         let subscope = RefCell::new(subscope);
 
 
-        let state_backend = Rc::new(S::new());
-        let state_handle = StateHandle::new(state_backend, &self.index().to_string());
+        let state_backend = match memory_budget {
+            Some(bytes) => Rc::new(S::with_memory_budget(bytes)),
+            None => Rc::new(S::new()),
+        };
+        let mut state_handle = StateHandle::new(state_backend, &self.index().to_string())
+            .create_sub_handle(&dataflow_index.to_string());
+        if let Some(bytes) = memory_budget {
+            state_handle = state_handle.with_memory_budget(bytes);
+        }
 
         let result = {
             let mut builder = Child::new(&subscope, self.clone(), logging.clone(), state_handle);